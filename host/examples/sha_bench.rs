@@ -0,0 +1,50 @@
+//! Cycle benchmark for the guest's SHA-256 accelerator path.
+//!
+//! Executes the `sha_bench` guest on a mainnet block and compares the cycles spent
+//! hashing the block header, every txid and the Merkle root in software (`bitcoin`
+//! crate) against the risc0 accelerator used by the proving guest.
+//!
+//! ```bash
+//! cargo run --release --example sha_bench -- 916202
+//! ```
+
+use methods::SHA_BENCH_ELF;
+use risc0_zkvm::{default_executor, ExecutorEnv};
+
+/// Full mainnet block (3,362 transactions) used when no height is given
+const DEFAULT_HEIGHT: u64 = 916202;
+
+fn main() -> anyhow::Result<()> {
+    let height = match std::env::args().nth(1) {
+        Some(arg) => arg.parse::<u64>()?,
+        None => DEFAULT_HEIGHT,
+    };
+
+    println!("🔍 Fetching Bitcoin block at height {}", height);
+    let hash_url = format!("https://blockstream.info/api/block-height/{}", height);
+    let block_hash = reqwest::blocking::get(&hash_url)?.text()?;
+    let block_url = format!("https://blockstream.info/api/block/{}/raw", block_hash);
+    let raw_block = reqwest::blocking::get(&block_url)?.bytes()?.to_vec();
+    println!("✅ Fetched block ({} bytes)", raw_block.len());
+
+    let env = ExecutorEnv::builder().write(&raw_block)?.build()?;
+    let session = default_executor().execute(env, SHA_BENCH_ELF)?;
+    let (tx_count, software_cycles, accelerated_cycles): (u32, u64, u64) =
+        session.journal.decode()?;
+
+    let saved = software_cycles.saturating_sub(accelerated_cycles);
+    println!(
+        "📊 SHA-256 cycles for block {} ({} transactions)",
+        height, tx_count
+    );
+    println!("   Software:    {:>12}", software_cycles);
+    println!("   Accelerated: {:>12}", accelerated_cycles);
+    println!(
+        "   Saved:       {:>12} ({:.1}%)",
+        saved,
+        saved as f64 * 100.0 / software_cycles.max(1) as f64
+    );
+    println!("   Total session cycles: {}", session.cycles());
+
+    Ok(())
+}
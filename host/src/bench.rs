@@ -0,0 +1,445 @@
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use methods::types::{
+    BlockProofJournal, ProofBundle, ProofStrategy, SearchingProof, TransactionPattern,
+};
+use methods::{BITCOIN_PROOFS_ELF, BITCOIN_PROOFS_ID};
+use risc0_zkvm::{default_executor, default_prover, ExecutorEnv};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::block_source::{decode_block_file, BlockSource};
+use crate::{bundle_metadata, padded_cycles, BitcoinBlockInput, ChainConfig};
+
+/// Current version of the [`BenchReport`] format
+const BENCH_REPORT_VERSION: u32 = 1;
+
+/// One block to benchmark
+pub struct BenchCase {
+    /// Fixture or block file the case came from
+    pub name: String,
+    pub block_height: u64,
+    pub strategy: ProofStrategy,
+    /// Raw block, when benchmarking a local file instead of fetching
+    pub raw_block: Option<Vec<u8>>,
+    /// Committed fields expected in the journal (from a proof fixture)
+    pub expected: Option<serde_json::Map<String, Value>>,
+}
+
+impl BenchCase {
+    /// A saved proof (e.g. `test_block_916202.json`): its height and strategy are
+    /// benchmarked, and its fields are checked against the new journal
+    pub fn from_fixture(path: &str) -> anyhow::Result<Self> {
+        let fixture: Value = serde_json::from_slice(&fs::read(path)?)?;
+        let expected = fixture
+            .as_object()
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Fixture {} is not a JSON object", path))?;
+        let block_height = expected
+            .get("block_height")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| anyhow::anyhow!("Fixture {} has no block_height", path))?;
+        let strategy = serde_json::from_value(
+            expected
+                .get("strategy")
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Fixture {} has no strategy", path))?,
+        )?;
+
+        Ok(Self {
+            name: path.to_string(),
+            block_height,
+            strategy,
+            raw_block: None,
+            expected: Some(expected),
+        })
+    }
+
+    /// A local `block_<height>.bin` / `.hex` file, benchmarked with a searching strategy
+    pub fn from_block_file(path: &str) -> anyhow::Result<Self> {
+        let block_height = Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix("block_"))
+            .and_then(|height| height.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Block file {} is not named block_<height>", path))?;
+
+        Ok(Self {
+            name: path.to_string(),
+            block_height,
+            strategy: ProofStrategy::Searching(SearchingProof {
+                pattern: TransactionPattern::All,
+            }),
+            raw_block: Some(decode_block_file(&fs::read(path)?)?),
+            expected: None,
+        })
+    }
+}
+
+/// Measurements for one [`BenchCase`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchEntry {
+    pub name: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_bytes: usize,
+    pub total_transactions: Option<u32>,
+    pub matching_count: Option<u32>,
+    /// User cycles, without continuation overhead or padding
+    pub user_cycles: u64,
+    /// Cycles after padding each segment to its power of two
+    pub total_cycles: u64,
+    pub segments: usize,
+    pub execute_ms: u64,
+    pub prove_ms: Option<u64>,
+    /// Size of the CBOR proof bundle
+    pub proof_bytes: Option<usize>,
+    /// Fixture fields the new journal no longer matches
+    pub fixture_mismatches: Vec<String>,
+}
+
+/// Change of one metric against the baseline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchDelta {
+    pub name: String,
+    pub metric: String,
+    pub baseline: u64,
+    pub current: u64,
+    pub change_percent: f64,
+}
+
+/// Machine-readable benchmark results, also usable as the next baseline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub format_version: u32,
+    pub created_at: u64,
+    pub host_version: String,
+    pub risc0_version: String,
+    /// Guest image ID (hex); cycles are only comparable across the same guest logic
+    pub image_id: String,
+    pub entries: Vec<BenchEntry>,
+    /// Changes against `--baseline`, if one was given
+    pub deltas: Vec<BenchDelta>,
+}
+
+impl BenchReport {
+    /// Compares deterministic metrics (cycles, segments, proof size) and wall times
+    /// for entries present in both reports
+    pub fn compare(&mut self, baseline: &BenchReport) {
+        for entry in &self.entries {
+            let Some(base) = baseline.entries.iter().find(|base| base.name == entry.name) else {
+                continue;
+            };
+            let metrics = [
+                (
+                    "user_cycles",
+                    Some(base.user_cycles),
+                    Some(entry.user_cycles),
+                ),
+                (
+                    "total_cycles",
+                    Some(base.total_cycles),
+                    Some(entry.total_cycles),
+                ),
+                (
+                    "segments",
+                    Some(base.segments as u64),
+                    Some(entry.segments as u64),
+                ),
+                ("execute_ms", Some(base.execute_ms), Some(entry.execute_ms)),
+                ("prove_ms", base.prove_ms, entry.prove_ms),
+                (
+                    "proof_bytes",
+                    base.proof_bytes.map(|bytes| bytes as u64),
+                    entry.proof_bytes.map(|bytes| bytes as u64),
+                ),
+            ];
+            for (metric, baseline, current) in metrics {
+                let (Some(baseline), Some(current)) = (baseline, current) else {
+                    continue;
+                };
+                self.deltas.push(BenchDelta {
+                    name: entry.name.clone(),
+                    metric: metric.to_string(),
+                    baseline,
+                    current,
+                    change_percent: change_percent(baseline, current),
+                });
+            }
+        }
+    }
+
+    /// Deltas of deterministic metrics that grew by more than `max_percent`
+    ///
+    /// Wall times are left out since they depend on the machine.
+    pub fn regressions(&self, max_percent: f64) -> Vec<&BenchDelta> {
+        self.deltas
+            .iter()
+            .filter(|delta| !delta.metric.ends_with("_ms") && delta.change_percent > max_percent)
+            .collect()
+    }
+}
+
+fn change_percent(baseline: u64, current: u64) -> f64 {
+    if baseline == 0 {
+        return if current == 0 { 0.0 } else { f64::INFINITY };
+    }
+    (current as f64 - baseline as f64) / baseline as f64 * 100.0
+}
+
+/// Executes (and optionally proves) each case, collecting a report
+pub fn run_bench(
+    cases: &[BenchCase],
+    prove: bool,
+    chain: &ChainConfig,
+    source: Option<&dyn BlockSource>,
+) -> anyhow::Result<BenchReport> {
+    let metadata = bundle_metadata();
+    let mut report = BenchReport {
+        format_version: BENCH_REPORT_VERSION,
+        created_at: metadata.created_at,
+        host_version: metadata.host_version,
+        risc0_version: metadata.risc0_version,
+        image_id: hex::encode(
+            BITCOIN_PROOFS_ID
+                .iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect::<Vec<u8>>(),
+        ),
+        entries: Vec::new(),
+        deltas: Vec::new(),
+    };
+
+    for case in cases {
+        println!("⏱️  {} (height {})", case.name, case.block_height);
+        let raw_block = match (&case.raw_block, source) {
+            (Some(raw_block), _) => raw_block.clone(),
+            (None, Some(source)) => source.block_at(case.block_height)?.1,
+            (None, None) => {
+                return Err(anyhow::anyhow!(
+                    "{} needs a block source to fetch height {}",
+                    case.name,
+                    case.block_height
+                ))
+            }
+        };
+        let entry = bench_case(case, raw_block, prove, chain)?;
+        println!(
+            "   {} user cycles, {} segments, executed in {} ms{}",
+            entry.user_cycles,
+            entry.segments,
+            entry.execute_ms,
+            entry
+                .prove_ms
+                .map(|ms| format!(", proved in {} ms", ms))
+                .unwrap_or_default()
+        );
+        for field in &entry.fixture_mismatches {
+            println!("   ⚠️  Journal field `{}` differs from the fixture", field);
+        }
+        report.entries.push(entry);
+    }
+
+    Ok(report)
+}
+
+fn bench_case(
+    case: &BenchCase,
+    raw_block: Vec<u8>,
+    prove: bool,
+    chain: &ChainConfig,
+) -> anyhow::Result<BenchEntry> {
+    let input = BitcoinBlockInput {
+        block_height: case.block_height,
+        network: chain.network,
+        signet_challenge: chain.signet_challenge.clone(),
+        strategy: case.strategy.clone(),
+    };
+    let guest_env = || {
+        ExecutorEnv::builder()
+            .write(&input)?
+            .write_slice(&raw_block)
+            .stdout(std::io::sink())
+            .build()
+    };
+
+    let started = Instant::now();
+    let session = default_executor().execute(guest_env()?, BITCOIN_PROOFS_ELF)?;
+    let execute_ms = started.elapsed().as_millis() as u64;
+
+    let journal: BlockProofJournal = session.journal.decode()?;
+    let (block_hash, total_transactions, matching_count) = match &journal {
+        Ok(proof) => (
+            proof.block_hash.clone(),
+            Some(proof.total_transactions),
+            Some(proof.matching_count),
+        ),
+        Err(failure) => (failure.block_hash.clone().unwrap_or_default(), None, None),
+    };
+
+    let fixture_mismatches = match &case.expected {
+        Some(expected) => {
+            let committed = match &journal {
+                Ok(proof) => serde_json::to_value(proof)?,
+                Err(failure) => serde_json::to_value(failure)?,
+            };
+            expected
+                .iter()
+                .filter(|(field, value)| committed.get(field.as_str()) != Some(*value))
+                .map(|(field, _)| field.clone())
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    let (prove_ms, proof_bytes) = if prove {
+        let started = Instant::now();
+        let receipt = default_prover()
+            .prove(guest_env()?, BITCOIN_PROOFS_ELF)?
+            .receipt;
+        let prove_ms = started.elapsed().as_millis() as u64;
+        let bundle = ProofBundle::new(receipt, BITCOIN_PROOFS_ID, chain.network, bundle_metadata())
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let proof_bytes = bundle.to_cbor().map_err(|e| anyhow::anyhow!(e))?.len();
+        (Some(prove_ms), Some(proof_bytes))
+    } else {
+        (None, None)
+    };
+
+    Ok(BenchEntry {
+        name: case.name.clone(),
+        block_height: case.block_height,
+        block_hash,
+        block_bytes: raw_block.len(),
+        total_transactions,
+        matching_count,
+        user_cycles: session.cycles(),
+        total_cycles: padded_cycles(&session),
+        segments: session.segments.len(),
+        execute_ms,
+        prove_ms,
+        proof_bytes,
+        fixture_mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, user_cycles: u64, prove_ms: Option<u64>) -> BenchEntry {
+        BenchEntry {
+            name: name.to_string(),
+            block_height: 916202,
+            block_hash: "00".repeat(32),
+            block_bytes: 1_000_000,
+            total_transactions: Some(3362),
+            matching_count: Some(0),
+            user_cycles,
+            total_cycles: user_cycles.next_power_of_two(),
+            segments: 2,
+            execute_ms: 1000,
+            prove_ms,
+            proof_bytes: prove_ms.map(|_| 250_000),
+            fixture_mismatches: Vec::new(),
+        }
+    }
+
+    fn report(entries: Vec<BenchEntry>) -> BenchReport {
+        BenchReport {
+            format_version: BENCH_REPORT_VERSION,
+            created_at: 1_700_000_000,
+            host_version: "test".to_string(),
+            risc0_version: "test".to_string(),
+            image_id: "00".repeat(32),
+            entries,
+            deltas: Vec::new(),
+        }
+    }
+
+    fn delta<'a>(report: &'a BenchReport, name: &str, metric: &str) -> Option<&'a BenchDelta> {
+        report
+            .deltas
+            .iter()
+            .find(|delta| delta.name == name && delta.metric == metric)
+    }
+
+    #[test]
+    fn test_change_percent() {
+        assert_eq!(change_percent(100, 150), 50.0);
+        assert_eq!(change_percent(200, 100), -50.0);
+        assert_eq!(change_percent(7, 7), 0.0);
+        assert_eq!(change_percent(0, 0), 0.0);
+        assert_eq!(change_percent(0, 1), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_compare_matches_entries_by_name() {
+        let baseline = report(vec![
+            entry("test_block_916202.json", 1_000_000, None),
+            entry("removed.json", 5, None),
+        ]);
+        let mut current = report(vec![
+            entry("test_block_916202.json", 1_100_000, Some(60_000)),
+            entry("added.json", 5, None),
+        ]);
+        current.compare(&baseline);
+
+        let cycles = delta(&current, "test_block_916202.json", "user_cycles").unwrap();
+        assert_eq!((cycles.baseline, cycles.current), (1_000_000, 1_100_000));
+        assert!((cycles.change_percent - 10.0).abs() < 1e-9);
+        assert!(delta(&current, "test_block_916202.json", "segments").is_some());
+        // Metrics missing from either side aren't compared
+        assert!(delta(&current, "test_block_916202.json", "prove_ms").is_none());
+        assert!(delta(&current, "test_block_916202.json", "proof_bytes").is_none());
+        // Entries without a baseline are skipped
+        assert!(current
+            .deltas
+            .iter()
+            .all(|delta| delta.name != "added.json"));
+    }
+
+    #[test]
+    fn test_compare_without_baseline_entries() {
+        let mut current = report(vec![entry("test_block_916202.json", 1_000_000, None)]);
+        current.compare(&report(Vec::new()));
+        assert!(current.deltas.is_empty());
+        assert!(current.regressions(0.0).is_empty());
+    }
+
+    #[test]
+    fn test_regressions() {
+        let mut baseline = report(vec![entry(
+            "test_block_916202.json",
+            1_000_000,
+            Some(60_000),
+        )]);
+        baseline.entries[0].proof_bytes = Some(0);
+        let mut current = report(vec![entry(
+            "test_block_916202.json",
+            1_030_000,
+            Some(90_000),
+        )]);
+        current.entries[0].execute_ms = 5000;
+        current.compare(&baseline);
+
+        // Growing from nothing is an infinite regression
+        let proof_bytes = delta(&current, "test_block_916202.json", "proof_bytes").unwrap();
+        assert_eq!(proof_bytes.change_percent, f64::INFINITY);
+
+        let metrics = |max_percent| {
+            let mut metrics: Vec<&str> = current
+                .regressions(max_percent)
+                .iter()
+                .map(|delta| delta.metric.as_str())
+                .collect();
+            metrics.sort();
+            metrics
+        };
+        // Wall times never count, however much they grew
+        assert_eq!(metrics(2.0), ["proof_bytes", "user_cycles"]);
+        assert_eq!(metrics(5.0), ["proof_bytes"]);
+    }
+}
@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::block_source::BlockSource;
+
+/// Credentials for Bitcoin Core's JSON-RPC interface
+#[derive(Debug, Clone)]
+pub enum RpcAuth {
+    /// No authentication (e.g. behind an authenticating proxy)
+    None,
+    /// `rpcuser` / `rpcpassword`
+    UserPass { user: String, password: String },
+    /// The `.cookie` file bitcoind writes to its data directory
+    Cookie(String),
+}
+
+impl RpcAuth {
+    /// Resolve credentials into a user/password pair
+    fn credentials(&self) -> anyhow::Result<Option<(String, String)>> {
+        match self {
+            RpcAuth::None => Ok(None),
+            RpcAuth::UserPass { user, password } => Ok(Some((user.clone(), password.clone()))),
+            RpcAuth::Cookie(path) => read_cookie(Path::new(path)).map(Some),
+        }
+    }
+}
+
+/// Reads a bitcoind cookie file (`__cookie__:<password>`)
+///
+/// Read on every request, since bitcoind rewrites the cookie on restart.
+fn read_cookie(path: &Path) -> anyhow::Result<(String, String)> {
+    let cookie = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read RPC cookie {}: {}", path.display(), e))?;
+    let (user, password) = cookie
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Malformed RPC cookie file {}", path.display()))?;
+    Ok((user.to_string(), password.to_string()))
+}
+
+/// JSON-RPC error object returned by bitcoind
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// JSON-RPC response envelope
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+/// Minimal blocking client for the Bitcoin Core RPC calls the prover needs
+#[derive(Debug, Clone)]
+pub struct BitcoinRpcClient {
+    url: String,
+    auth: RpcAuth,
+    client: reqwest::blocking::Client,
+}
+
+impl BitcoinRpcClient {
+    pub fn new(url: &str, auth: RpcAuth) -> Self {
+        Self {
+            url: url.to_string(),
+            auth,
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    /// `getblockhash <height>`
+    pub fn get_block_hash(&self, height: u64) -> anyhow::Result<String> {
+        let result = self.call("getblockhash", json!([height]))?;
+        result
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("getblockhash returned a non-string result"))
+    }
+
+    /// `getblock <hash> 0`, decoded from hex into raw block bytes
+    pub fn get_raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        let result = self.call("getblock", json!([block_hash, 0]))?;
+        let block_hex = result
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("getblock returned a non-string result"))?;
+        Ok(hex::decode(block_hex)?)
+    }
+
+    /// `getblockcount`
+    pub fn get_block_count(&self) -> anyhow::Result<u64> {
+        let result = self.call("getblockcount", json!([]))?;
+        result
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("getblockcount returned a non-integer result"))
+    }
+
+    /// Performs a single JSON-RPC call and unwraps the result
+    fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let body = json!({
+            "jsonrpc": "1.0",
+            "id": "bitcoin-zk-proofs",
+            "method": method,
+            "params": params,
+        });
+
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some((user, password)) = self.auth.credentials()? {
+            request = request.basic_auth(user, Some(password));
+        }
+
+        let response = request
+            .send()
+            .map_err(|e| anyhow::anyhow!("RPC {} to {} failed: {}", method, self.url, e))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(anyhow::anyhow!("RPC {} rejected: unauthorized", method));
+        }
+
+        // bitcoind reports RPC errors with a non-2xx status and a JSON body
+        let response: RpcResponse = response.json().map_err(|e| {
+            anyhow::anyhow!(
+                "RPC {} returned an invalid response ({}): {}",
+                method,
+                status,
+                e
+            )
+        })?;
+        if let Some(error) = response.error {
+            return Err(anyhow::anyhow!(
+                "RPC {} error {}: {}",
+                method,
+                error.code,
+                error.message
+            ));
+        }
+        response
+            .result
+            .ok_or_else(|| anyhow::anyhow!("RPC {} returned no result", method))
+    }
+}
+
+impl BlockSource for BitcoinRpcClient {
+    fn name(&self) -> String {
+        format!("bitcoin core rpc ({})", self.url)
+    }
+
+    fn block_hash(&self, height: u64) -> anyhow::Result<String> {
+        self.get_block_hash(height)
+    }
+
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        self.get_raw_block(block_hash)
+    }
+
+    fn tip_height(&self) -> anyhow::Result<u64> {
+        self.get_block_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+
+    use super::*;
+    use crate::block_source::{block_hash_of, testing};
+
+    /// Request seen by the mock server: the `Authorization` header and the JSON body
+    type Seen = Arc<Mutex<Vec<(Option<String>, Value)>>>;
+
+    /// Serves one HTTP response per connection from `respond(auth, body) -> (status, body)`
+    fn mock_server(
+        respond: impl Fn(Option<&str>, &Value) -> (u16, String) + Send + 'static,
+    ) -> (String, Seen) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let seen = Seen::default();
+        let recorded = seen.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut auth = None;
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(": ").unwrap_or((line, ""));
+                    match name.to_ascii_lowercase().as_str() {
+                        "authorization" => auth = Some(value.to_string()),
+                        "content-length" => length = value.parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+
+                let (status, response) = respond(auth.as_deref(), &body);
+                recorded.lock().unwrap().push((auth, body));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        (url, seen)
+    }
+
+    const BLOCK_HASH: &str = "00000000839a8e6886ab5951d76f411475428afc90947ee320161bbf18eb6048";
+
+    /// bitcoind's answer for `result`
+    fn ok(result: Value) -> (u16, String) {
+        (
+            200,
+            json!({ "result": result, "error": null, "id": "t" }).to_string(),
+        )
+    }
+
+    /// Mock node with the given raw blocks by height
+    fn mock_node(blocks: Vec<Vec<u8>>, hashes: Vec<String>) -> (String, Seen) {
+        mock_server(move |_, body| {
+            let params = &body["params"];
+            match body["method"].as_str().unwrap() {
+                "getblockcount" => ok(json!(blocks.len() - 1)),
+                "getblockhash" => ok(json!(hashes[params[0].as_u64().unwrap() as usize])),
+                "getblock" => {
+                    assert_eq!(params[1], 0, "blocks are fetched raw");
+                    let index = hashes.iter().position(|hash| params[0] == *hash).unwrap();
+                    ok(json!(hex::encode(&blocks[index])))
+                }
+                method => panic!("unexpected method {}", method),
+            }
+        })
+    }
+
+    #[test]
+    fn test_user_password_auth() {
+        let (url, seen) = mock_server(|auth, _| match auth {
+            Some("Basic YWxpY2U6c2VjcmV0") => ok(json!(BLOCK_HASH)),
+            _ => (401, String::new()),
+        });
+        let auth = RpcAuth::UserPass {
+            user: "alice".to_string(),
+            password: "secret".to_string(),
+        };
+        assert_eq!(
+            BitcoinRpcClient::new(&url, auth).get_block_hash(1).unwrap(),
+            BLOCK_HASH
+        );
+
+        let (_, body) = &seen.lock().unwrap()[0];
+        assert_eq!(body["method"], "getblockhash");
+        assert_eq!(body["params"], json!([1]));
+    }
+
+    #[test]
+    fn test_cookie_auth_rereads_cookie() {
+        let dir = tempfile::tempdir().unwrap();
+        let cookie = dir.path().join(".cookie");
+        fs::write(&cookie, "__cookie__:first\n").unwrap();
+
+        let (url, seen) = mock_server(|_, _| ok(json!(BLOCK_HASH)));
+        let client = BitcoinRpcClient::new(&url, RpcAuth::Cookie(cookie.display().to_string()));
+        client.get_block_hash(1).unwrap();
+        // bitcoind writes a new cookie when it restarts
+        fs::write(&cookie, "__cookie__:second").unwrap();
+        client.get_block_hash(1).unwrap();
+
+        let seen = seen.lock().unwrap();
+        // base64 of `__cookie__:first` and `__cookie__:second`
+        assert_eq!(seen[0].0.as_deref(), Some("Basic X19jb29raWVfXzpmaXJzdA=="));
+        assert_eq!(seen[1].0.as_deref(), Some("Basic X19jb29raWVfXzpzZWNvbmQ="));
+    }
+
+    #[test]
+    fn test_missing_cookie() {
+        let client = BitcoinRpcClient::new(
+            "http://127.0.0.1:1",
+            RpcAuth::Cookie("/nonexistent/.cookie".to_string()),
+        );
+        let error = client.get_block_hash(1).unwrap_err().to_string();
+        assert!(error.contains("Failed to read RPC cookie"), "{}", error);
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let (url, _) = mock_server(|_, _| (401, String::new()));
+        let client = BitcoinRpcClient::new(&url, RpcAuth::None);
+        let error = client.get_block_hash(1).unwrap_err().to_string();
+        assert_eq!(error, "RPC getblockhash rejected: unauthorized");
+    }
+
+    #[test]
+    fn test_rpc_error() {
+        let (url, _) = mock_server(|_, _| {
+            let error = json!({ "code": -8, "message": "Block height out of range" });
+            (500, json!({ "result": null, "error": error }).to_string())
+        });
+        let client = BitcoinRpcClient::new(&url, RpcAuth::None);
+        let error = client.get_block_hash(99).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "RPC getblockhash error -8: Block height out of range"
+        );
+    }
+
+    #[test]
+    fn test_raw_block() {
+        let (url, seen) = mock_server(|_, _| ok(json!("00ff10")));
+        let client = BitcoinRpcClient::new(&url, RpcAuth::None);
+        assert_eq!(
+            client.get_raw_block(BLOCK_HASH).unwrap(),
+            [0x00, 0xff, 0x10]
+        );
+
+        let (_, body) = &seen.lock().unwrap()[0];
+        assert_eq!(body["method"], "getblock");
+        assert_eq!(body["params"], json!([BLOCK_HASH, 0]));
+    }
+
+    #[test]
+    fn test_block_source() {
+        let genesis = testing::block(BlockHash::all_zeros(), 0);
+        let genesis_hash = block_hash_of(&genesis).unwrap();
+        let child = testing::block(genesis_hash.parse().unwrap(), 1);
+        let child_hash = block_hash_of(&child).unwrap();
+        let (url, seen) = mock_node(
+            vec![genesis, child.clone()],
+            vec![genesis_hash, child_hash.clone()],
+        );
+
+        let client = BitcoinRpcClient::new(&url, RpcAuth::None);
+        assert_eq!(client.tip_height().unwrap(), 1);
+        assert_eq!(client.block_at(1).unwrap(), (child_hash.clone(), child));
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen[1].1["method"], "getblockhash");
+        assert_eq!(seen[1].1["params"], json!([1]));
+        assert_eq!(seen[2].1["method"], "getblock");
+        assert_eq!(seen[2].1["params"], json!([child_hash, 0]));
+    }
+
+    #[test]
+    fn test_block_source_rejects_wrong_block() {
+        let block = testing::block(BlockHash::all_zeros(), 0);
+        let other = testing::block(BlockHash::all_zeros(), 1);
+        let other_hash = block_hash_of(&other).unwrap();
+        // The node answers the hash of `other` with the bytes of `block`
+        let (url, _) = mock_node(vec![block], vec![other_hash.clone()]);
+
+        let client = BitcoinRpcClient::new(&url, RpcAuth::None);
+        let error = client.block_at(0).unwrap_err().to_string();
+        assert!(
+            error.contains(&format!("when asked for {}", other_hash)),
+            "{}",
+            error
+        );
+    }
+}
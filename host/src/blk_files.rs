@@ -0,0 +1,446 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use bitcoin::block::Header;
+use bitcoin::hashes::Hash;
+use bitcoin::pow::Work;
+use bitcoin::BlockHash;
+
+use crate::block_source::BlockSource;
+
+/// Size of the magic + length prefix before every block record
+const RECORD_PREFIX_LEN: usize = 8;
+
+/// Where a block's bytes live on disk
+#[derive(Debug, Clone, Copy)]
+struct BlockLocation {
+    file: usize,
+    offset: u64,
+    size: u32,
+}
+
+/// Bitcoin Core's `blocks/` directory, read straight from `blk*.dat`
+///
+/// Each file is a sequence of `magic | size (LE u32) | block` records, optionally
+/// XOR-obfuscated with the 8-byte key in `xor.dat` (Bitcoin Core 28+). Files are
+/// indexed once on open by reading only the record prefixes and headers; heights come
+/// from chaining `prev_blockhash` back to genesis and keeping the most-work chain, since
+/// blocks are stored in arrival order and include stale blocks.
+///
+/// The node doesn't need to be running, but it shouldn't be pruning the files being read.
+pub struct BlkFilesSource {
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+    xor_key: [u8; 8],
+    locations: HashMap<BlockHash, BlockLocation>,
+    best_chain: Vec<BlockHash>, // best_chain[h] is the block at height h
+}
+
+impl BlkFilesSource {
+    /// Indexes every `blk*.dat` file in `dir` whose records carry `magic`
+    pub fn open(dir: impl AsRef<Path>, magic: [u8; 4]) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let xor_key = read_xor_key(&dir)?;
+
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("blk") && name.ends_with(".dat"))
+            })
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No blk*.dat files in {}", dir.display()));
+        }
+
+        let mut locations = HashMap::new();
+        let mut headers = HashMap::new();
+        for (file_index, path) in files.iter().enumerate() {
+            index_file(
+                file_index,
+                path,
+                &xor_key,
+                magic,
+                &mut locations,
+                &mut headers,
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to index {}: {}", path.display(), e))?;
+        }
+        let best_chain = best_chain(&headers, &locations);
+
+        Ok(Self {
+            dir,
+            files,
+            xor_key,
+            locations,
+            best_chain,
+        })
+    }
+}
+
+impl BlockSource for BlkFilesSource {
+    fn name(&self) -> String {
+        format!("blk files ({})", self.dir.display())
+    }
+
+    fn block_hash(&self, height: u64) -> anyhow::Result<String> {
+        self.best_chain
+            .get(height as usize)
+            .map(BlockHash::to_string)
+            .ok_or_else(|| anyhow::anyhow!("No block at height {} in {}", height, self.name()))
+    }
+
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        let hash: BlockHash = block_hash.parse()?;
+        let location = self
+            .locations
+            .get(&hash)
+            .ok_or_else(|| anyhow::anyhow!("Block {} not in {}", block_hash, self.name()))?;
+
+        let mut file = File::open(&self.files[location.file])?;
+        read_at(
+            &mut file,
+            &self.xor_key,
+            location.offset,
+            location.size as usize,
+        )
+    }
+
+    fn tip_height(&self) -> anyhow::Result<u64> {
+        self.best_chain
+            .len()
+            .checked_sub(1)
+            .map(|height| height as u64)
+            .ok_or_else(|| anyhow::anyhow!("No chain from genesis in {}", self.name()))
+    }
+}
+
+/// Reads the obfuscation key, or the all-zero key if `xor.dat` doesn't exist
+fn read_xor_key(dir: &Path) -> anyhow::Result<[u8; 8]> {
+    let path = dir.join("xor.dat");
+    if !path.exists() {
+        return Ok([0; 8]);
+    }
+    let key = fs::read(&path)?;
+    key.try_into().map_err(|key: Vec<u8>| {
+        anyhow::anyhow!(
+            "{} should hold an 8-byte key, found {} bytes",
+            path.display(),
+            key.len()
+        )
+    })
+}
+
+/// Reads `len` bytes at `offset`, undoing the XOR obfuscation
+///
+/// The key is applied by absolute file position, so it's indexed by `offset + i`.
+fn read_at(file: &mut File, xor_key: &[u8; 8], offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte ^= xor_key[((offset + i as u64) % 8) as usize];
+    }
+    Ok(buf)
+}
+
+/// Records the location and header of every block in one file
+fn index_file(
+    file_index: usize,
+    path: &Path,
+    xor_key: &[u8; 8],
+    magic: [u8; 4],
+    locations: &mut HashMap<BlockHash, BlockLocation>,
+    headers: &mut HashMap<BlockHash, Header>,
+) -> anyhow::Result<()> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut offset = 0u64;
+
+    while offset + (RECORD_PREFIX_LEN + 80) as u64 <= file_len {
+        let prefix = read_at(&mut file, xor_key, offset, RECORD_PREFIX_LEN + 80)?;
+        if prefix[..4] != magic {
+            // Files are preallocated; zeroes mark the unused tail
+            if prefix[..4] == [0; 4] {
+                break;
+            }
+            return Err(anyhow::anyhow!(
+                "Unexpected magic {} at offset {} (wrong network or XOR key?)",
+                hex::encode(&prefix[..4]),
+                offset
+            ));
+        }
+
+        let size = u32::from_le_bytes(prefix[4..8].try_into().unwrap());
+        let block_offset = offset + RECORD_PREFIX_LEN as u64;
+        if block_offset + size as u64 > file_len {
+            // Partially written record at the end of the newest file
+            break;
+        }
+
+        let header: Header = bitcoin::consensus::deserialize(&prefix[RECORD_PREFIX_LEN..])?;
+        let hash = header.block_hash();
+        // A block stored twice keeps its first record, which decides equal-work ties
+        locations.entry(hash).or_insert(BlockLocation {
+            file: file_index,
+            offset: block_offset,
+            size,
+        });
+        headers.insert(hash, header);
+
+        offset = block_offset + size as u64;
+    }
+
+    Ok(())
+}
+
+/// Links headers from genesis and returns the most-work chain, indexed by height
+///
+/// Blocks whose ancestry doesn't reach genesis (e.g. a partial copy of the directory)
+/// are left out. Between tips with equal work, the one stored first wins, as Bitcoin
+/// Core keeps the tip it received first and stores blocks in arrival order.
+fn best_chain(
+    headers: &HashMap<BlockHash, Header>,
+    locations: &HashMap<BlockHash, BlockLocation>,
+) -> Vec<BlockHash> {
+    let mut children: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+    for (hash, header) in headers {
+        children
+            .entry(header.prev_blockhash)
+            .or_default()
+            .push(*hash);
+    }
+
+    let mut chain_work: HashMap<BlockHash, Work> = HashMap::new();
+    let mut queue: VecDeque<BlockHash> = VecDeque::new();
+    for genesis in children.get(&BlockHash::all_zeros()).into_iter().flatten() {
+        chain_work.insert(*genesis, headers[genesis].work());
+        queue.push_back(*genesis);
+    }
+    while let Some(hash) = queue.pop_front() {
+        let work = chain_work[&hash];
+        for child in children.get(&hash).into_iter().flatten() {
+            chain_work.insert(*child, work + headers[child].work());
+            queue.push_back(*child);
+        }
+    }
+
+    let Some(tip) = chain_work
+        .iter()
+        .max_by_key(|(hash, work)| {
+            let location = &locations[*hash];
+            (**work, Reverse((location.file, location.offset)))
+        })
+        .map(|(hash, _)| *hash)
+    else {
+        return Vec::new();
+    };
+
+    let mut chain = vec![tip];
+    let mut hash = tip;
+    while let Some(header) = headers.get(&hash) {
+        if header.prev_blockhash == BlockHash::all_zeros() {
+            break;
+        }
+        hash = header.prev_blockhash;
+        chain.push(hash);
+    }
+    chain.reverse();
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_source::{block_hash_of, testing};
+
+    use super::*;
+
+    /// Regtest network magic
+    const MAGIC: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
+
+    /// Writes `magic | size | block` records followed by `tail`, XORed with `xor_key`
+    fn write_blk(path: &Path, blocks: &[&[u8]], tail: &[u8], xor_key: &[u8; 8]) {
+        let mut data = Vec::new();
+        for block in blocks {
+            data.extend_from_slice(&MAGIC);
+            data.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            data.extend_from_slice(block);
+        }
+        data.extend_from_slice(tail);
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte ^= xor_key[i % 8];
+        }
+        fs::write(path, data).unwrap();
+    }
+
+    /// A block on `prev`, with its nBits replaced to change its work
+    fn block_with_bits(prev: &str, tag: u32, bits: u32) -> Vec<u8> {
+        let mut raw_block = testing::block(prev.parse().unwrap(), tag);
+        raw_block[72..76].copy_from_slice(&bits.to_le_bytes());
+        raw_block
+    }
+
+    /// Chain of `count` regtest-difficulty blocks from genesis
+    fn chain(count: u32) -> Vec<Vec<u8>> {
+        let mut blocks: Vec<Vec<u8>> = Vec::new();
+        for tag in 0..count {
+            let prev = match blocks.last() {
+                Some(parent) => block_hash_of(parent).unwrap(),
+                None => BlockHash::all_zeros().to_string(),
+            };
+            blocks.push(block_with_bits(&prev, tag, 0x207fffff));
+        }
+        blocks
+    }
+
+    fn assert_chain(source: &BlkFilesSource, blocks: &[Vec<u8>]) {
+        assert_eq!(source.tip_height().unwrap(), blocks.len() as u64 - 1);
+        for (height, block) in blocks.iter().enumerate() {
+            let (_, raw_block) = source.block_at(height as u64).unwrap();
+            assert_eq!(&raw_block, block, "height {}", height);
+        }
+    }
+
+    #[test]
+    fn test_records_across_files() {
+        let blocks = chain(4);
+        let dir = tempfile::tempdir().unwrap();
+        // Preallocated zeroes after the last record
+        write_blk(
+            &dir.path().join("blk00000.dat"),
+            &[&blocks[0], &blocks[1]],
+            &[0; 64],
+            &[0; 8],
+        );
+        // A partially written record at the end of the newest file
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend_from_slice(&1000u32.to_le_bytes());
+        truncated.extend_from_slice(&chain(5)[4][..100]);
+        write_blk(
+            &dir.path().join("blk00001.dat"),
+            &[&blocks[3], &blocks[2]],
+            &truncated,
+            &[0; 8],
+        );
+        fs::write(dir.path().join("rev00000.dat"), b"undo data").unwrap();
+
+        let source = BlkFilesSource::open(dir.path(), MAGIC).unwrap();
+        assert_eq!(source.locations.len(), 4);
+        assert_chain(&source, &blocks);
+    }
+
+    #[test]
+    fn test_xor_obfuscation() {
+        let blocks = chain(3);
+        let xor_key = [0x13, 0x57, 0x9b, 0xdf, 0x02, 0x46, 0x8a, 0xce];
+        let dir = tempfile::tempdir().unwrap();
+        write_blk(
+            &dir.path().join("blk00000.dat"),
+            &[&blocks[0], &blocks[1], &blocks[2]],
+            &[0; 16],
+            &xor_key,
+        );
+        fs::write(dir.path().join("xor.dat"), xor_key).unwrap();
+        let source = BlkFilesSource::open(dir.path(), MAGIC).unwrap();
+        assert_chain(&source, &blocks);
+
+        // Without the key the magic doesn't match
+        fs::remove_file(dir.path().join("xor.dat")).unwrap();
+        let error = BlkFilesSource::open(dir.path(), MAGIC)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Unexpected magic"), "{}", error);
+
+        fs::write(dir.path().join("xor.dat"), [1, 2, 3]).unwrap();
+        let error = BlkFilesSource::open(dir.path(), MAGIC)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("8-byte key, found 3 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn test_wrong_network() {
+        let blocks = chain(1);
+        let dir = tempfile::tempdir().unwrap();
+        write_blk(
+            &dir.path().join("blk00000.dat"),
+            &[&blocks[0]],
+            &[],
+            &[0; 8],
+        );
+        let mainnet = [0xf9, 0xbe, 0xb4, 0xd9];
+        assert!(BlkFilesSource::open(dir.path(), mainnet).is_err());
+        assert!(BlkFilesSource::open(tempfile::tempdir().unwrap().path(), MAGIC).is_err());
+    }
+
+    #[test]
+    fn test_best_chain_by_work() {
+        let blocks = chain(4);
+        let genesis = block_hash_of(&blocks[0]).unwrap();
+        // Shorter than the regtest-difficulty branch, but with far more work
+        let heavy = block_with_bits(&genesis, 100, 0x1d00ffff);
+        let heavy_child = block_with_bits(&block_hash_of(&heavy).unwrap(), 101, 0x207fffff);
+        // Its parent is missing, so it can't be placed at a height
+        let detached = block_with_bits(&"11".repeat(32), 200, 0x1d00ffff);
+
+        let dir = tempfile::tempdir().unwrap();
+        // Blocks are stored in arrival order, stale ones included
+        write_blk(
+            &dir.path().join("blk00000.dat"),
+            &[&blocks[0], &blocks[1], &heavy, &blocks[2], &detached],
+            &[],
+            &[0; 8],
+        );
+        write_blk(
+            &dir.path().join("blk00001.dat"),
+            &[&blocks[3], &heavy_child],
+            &[],
+            &[0; 8],
+        );
+
+        let source = BlkFilesSource::open(dir.path(), MAGIC).unwrap();
+        assert_chain(&source, &[blocks[0].clone(), heavy, heavy_child]);
+        // Stale and detached blocks can still be read by hash
+        source
+            .raw_block(&block_hash_of(&blocks[3]).unwrap())
+            .unwrap();
+        source
+            .raw_block(&block_hash_of(&detached).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_equal_work_tie_goes_to_first_stored() {
+        let genesis = chain(1).remove(0);
+        let genesis_hash = block_hash_of(&genesis).unwrap();
+        let first = block_with_bits(&genesis_hash, 1, 0x207fffff);
+        let second = block_with_bits(&genesis_hash, 2, 0x207fffff);
+
+        for (blk0, blk1, tip) in [
+            (vec![&genesis, &first, &second], vec![], &first),
+            (vec![&genesis, &second, &first], vec![], &second),
+            (vec![&genesis, &second], vec![&first], &second),
+            // A stale duplicate of a block doesn't move its place in line
+            (vec![&genesis, &first], vec![&second, &first], &first),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let blk0: Vec<&[u8]> = blk0.into_iter().map(Vec::as_slice).collect();
+            let blk1: Vec<&[u8]> = blk1.into_iter().map(Vec::as_slice).collect();
+            write_blk(&dir.path().join("blk00000.dat"), &blk0, &[], &[0; 8]);
+            write_blk(&dir.path().join("blk00001.dat"), &blk1, &[], &[0; 8]);
+
+            // Each open hashes with fresh HashMap seeds
+            for _ in 0..8 {
+                let source = BlkFilesSource::open(dir.path(), MAGIC).unwrap();
+                assert_chain(&source, &[genesis.clone(), tip.clone()]);
+            }
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use bitcoin::block::Header;
+
+/// Provider of raw Bitcoin blocks
+///
+/// Proving code only talks to this trait, so providers can be swapped (or faked in
+/// tests) without touching it. Hashes are hex strings in the usual display order.
+pub trait BlockSource: Send + Sync {
+    /// Short description for logs
+    fn name(&self) -> String;
+
+    /// Hash of the block at `height` on the source's best chain
+    fn block_hash(&self, height: u64) -> anyhow::Result<String>;
+
+    /// Raw consensus-encoded block with the given hash
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>>;
+
+    /// Height of the source's best chain tip
+    fn tip_height(&self) -> anyhow::Result<u64>;
+
+    /// Hash and raw block at `height`, checking the block actually has that hash
+    fn block_at(&self, height: u64) -> anyhow::Result<(String, Vec<u8>)> {
+        let block_hash = self.block_hash(height)?;
+        let raw_block = self.raw_block(&block_hash)?;
+        let actual_hash = block_hash_of(&raw_block)?;
+        if actual_hash != block_hash {
+            return Err(anyhow::anyhow!(
+                "{} returned block {} when asked for {}",
+                self.name(),
+                actual_hash,
+                block_hash
+            ));
+        }
+        Ok((block_hash, raw_block))
+    }
+}
+
+/// Computes the block hash of a raw block from its header
+pub fn block_hash_of(raw_block: &[u8]) -> anyhow::Result<String> {
+    let header_bytes = raw_block
+        .get(..80)
+        .ok_or_else(|| anyhow::anyhow!("Block is shorter than a header"))?;
+    let header: Header = bitcoin::consensus::deserialize(header_bytes)?;
+    Ok(header.block_hash().to_string())
+}
+
+/// Fully decodes a raw block and checks its Merkle root and witness commitment
+///
+/// The guest does the authoritative check; this just fails fast on the host.
+pub fn sanity_check_block(raw_block: &[u8]) -> anyhow::Result<bitcoin::Block> {
+    let block: bitcoin::Block = bitcoin::consensus::deserialize(raw_block)
+        .map_err(|e| anyhow::anyhow!("Block does not parse: {}", e))?;
+    if !block.check_merkle_root() {
+        return Err(anyhow::anyhow!(
+            "Block {} has a Merkle root that doesn't match its transactions",
+            block.block_hash()
+        ));
+    }
+    if !block.check_witness_commitment() {
+        return Err(anyhow::anyhow!(
+            "Block {} has an invalid witness commitment",
+            block.block_hash()
+        ));
+    }
+    Ok(block)
+}
+
+/// Decodes a block file holding either raw bytes or hex
+pub fn decode_block_file(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let text = data.trim_ascii();
+    if !text.is_empty() && text.iter().all(u8::is_ascii_hexdigit) {
+        Ok(hex::decode(text)?)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+/// Esplora HTTP API (Blockstream by default)
+pub struct EsploraSource {
+    base_url: String,
+    client: reqwest::blocking::Client,
+}
+
+impl EsploraSource {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: reqwest::blocking::Client::new(),
+        }
+    }
+
+    fn get(&self, path: &str) -> anyhow::Result<reqwest::blocking::Response> {
+        let url = format!("{}{}", self.base_url, path);
+        let response = self.client.get(&url).send()?;
+        response
+            .error_for_status()
+            .map_err(|e| anyhow::anyhow!("GET {} failed: {}", url, e))
+    }
+}
+
+impl BlockSource for EsploraSource {
+    fn name(&self) -> String {
+        format!("esplora ({})", self.base_url)
+    }
+
+    fn block_hash(&self, height: u64) -> anyhow::Result<String> {
+        Ok(self
+            .get(&format!("/block-height/{}", height))?
+            .text()?
+            .trim()
+            .to_string())
+    }
+
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .get(&format!("/block/{}/raw", block_hash))?
+            .bytes()?
+            .to_vec())
+    }
+
+    fn tip_height(&self) -> anyhow::Result<u64> {
+        Ok(self.get("/blocks/tip/height")?.text()?.trim().parse()?)
+    }
+}
+
+/// Directory of `block_<height>.bin` (raw) or `block_<height>.hex` files
+///
+/// Files are indexed by name when opened and only read when their block is requested.
+pub struct LocalDirSource {
+    dir: PathBuf,
+    by_height: BTreeMap<u64, PathBuf>,
+    /// Hashes of the files read so far
+    by_hash: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl LocalDirSource {
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let mut by_height = BTreeMap::new();
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(height) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix("block_"))
+                .and_then(|height| height.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("bin" | "hex")
+            ) {
+                by_height.insert(height, path);
+            }
+        }
+
+        Ok(Self {
+            dir,
+            by_height,
+            by_hash: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Reads and decodes a block file, remembering its hash
+    fn read(&self, path: &Path) -> anyhow::Result<(String, Vec<u8>)> {
+        let raw_block = decode_block_file(&fs::read(path)?)?;
+        let block_hash = block_hash_of(&raw_block)
+            .map_err(|e| anyhow::anyhow!("Invalid block file {}: {}", path.display(), e))?;
+        self.by_hash
+            .lock()
+            .unwrap()
+            .insert(block_hash.clone(), path.to_path_buf());
+        Ok((block_hash, raw_block))
+    }
+}
+
+impl BlockSource for LocalDirSource {
+    fn name(&self) -> String {
+        format!("directory ({})", self.dir.display())
+    }
+
+    fn block_hash(&self, height: u64) -> anyhow::Result<String> {
+        let path = self
+            .by_height
+            .get(&height)
+            .ok_or_else(|| anyhow::anyhow!("No block at height {} in {}", height, self.name()))?;
+        Ok(self.read(path)?.0)
+    }
+
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        let known = self.by_hash.lock().unwrap().get(block_hash).cloned();
+        if let Some(path) = known {
+            return Ok(self.read(&path)?.1);
+        }
+
+        // Not looked up by height yet: search the files that haven't been read
+        for path in self.by_height.values() {
+            if self
+                .by_hash
+                .lock()
+                .unwrap()
+                .values()
+                .any(|read| read == path)
+            {
+                continue;
+            }
+            // One unreadable file must not hide the others
+            let (hash, raw_block) = match self.read(path) {
+                Ok(read) => read,
+                Err(e) => {
+                    println!("⚠️  Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if hash == block_hash {
+                return Ok(raw_block);
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Block {} not in {}",
+            block_hash,
+            self.name()
+        ))
+    }
+
+    fn tip_height(&self) -> anyhow::Result<u64> {
+        self.by_height
+            .keys()
+            .next_back()
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("No blocks in {}", self.name()))
+    }
+}
+
+/// Blocks held in memory, for offline runs and tests
+#[derive(Default)]
+pub struct MemorySource {
+    by_height: BTreeMap<u64, String>,
+    by_hash: HashMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a raw block at `height`, returning its hash
+    pub fn insert(&mut self, height: u64, raw_block: Vec<u8>) -> anyhow::Result<String> {
+        let block_hash = block_hash_of(&raw_block)?;
+        self.by_height.insert(height, block_hash.clone());
+        self.by_hash.insert(block_hash.clone(), raw_block);
+        Ok(block_hash)
+    }
+}
+
+impl BlockSource for MemorySource {
+    fn name(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn block_hash(&self, height: u64) -> anyhow::Result<String> {
+        self.by_height
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("No block at height {} in memory", height))
+    }
+
+    fn raw_block(&self, block_hash: &str) -> anyhow::Result<Vec<u8>> {
+        self.by_hash
+            .get(block_hash)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Block {} not in memory", block_hash))
+    }
+
+    fn tip_height(&self) -> anyhow::Result<u64> {
+        self.by_height
+            .keys()
+            .next_back()
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("No blocks in memory"))
+    }
+}
+
+/// Small synthetic blocks and proofs for tests
+#[cfg(test)]
+pub mod testing {
+    use bitcoin::block::{Header, Version};
+    use bitcoin::hashes::Hash;
+    use bitcoin::script::Builder;
+    use bitcoin::{
+        absolute, transaction, Amount, Block, BlockHash, CompactTarget, OutPoint, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    use methods::types::{
+        BitcoinBlockProof, BitcoinNetwork, BlockProofJournal, BundleMetadata, MatchingTransaction,
+        ProofBundle, ProofStrategy, SearchingProof, TransactionPattern, TransactionType,
+    };
+    use risc0_zkvm::{FakeReceipt, InnerReceipt, Receipt, ReceiptClaim};
+
+    use super::MemorySource;
+
+    /// Raw block on top of `prev` with only a coinbase
+    ///
+    /// `tag` goes into the coinbase, so blocks on competing branches get different hashes.
+    pub fn block(prev: BlockHash, tag: u32) -> Vec<u8> {
+        let coinbase = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(tag as i64).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_0000_0000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let header = Header {
+            version: Version::TWO,
+            prev_blockhash: prev,
+            merkle_root: coinbase.compute_txid().into(),
+            time: 1_700_000_000 + tag,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        };
+        bitcoin::consensus::serialize(&Block {
+            header,
+            txdata: vec![coinbase],
+        })
+    }
+
+    /// Inserts `count` blocks at `start..`, built on the block at `start - 1` (or on
+    /// nothing at height 0), returning their hashes
+    pub fn extend(source: &mut MemorySource, start: u64, count: u64, tag: u32) -> Vec<String> {
+        let mut prev = match start.checked_sub(1) {
+            Some(parent) => source.by_height[&parent].parse().unwrap(),
+            None => BlockHash::all_zeros(),
+        };
+        (start..start + count)
+            .map(|height| {
+                let raw_block = block(prev, tag.wrapping_mul(1000).wrapping_add(height as u32));
+                let block_hash = source.insert(height, raw_block).unwrap();
+                prev = block_hash.parse().unwrap();
+                block_hash
+            })
+            .collect()
+    }
+
+    /// Replaces the best chain from `start` on with `count` blocks of a competing branch
+    pub fn fork(source: &mut MemorySource, start: u64, count: u64, tag: u32) -> Vec<String> {
+        source.by_height.retain(|&height, _| height < start);
+        extend(source, start, count, tag)
+    }
+
+    /// Searching proof of a block with one matching transaction, as the daemon writes it
+    pub fn proof(height: u64, block_hash: &str, network: BitcoinNetwork) -> BitcoinBlockProof {
+        BitcoinBlockProof {
+            block_hash: block_hash.to_string(),
+            block_height: height,
+            network_magic: network.magic(),
+            signet_challenge_hash: None,
+            strategy: ProofStrategy::Searching(SearchingProof {
+                pattern: TransactionPattern::All,
+            }),
+            matching_transactions: vec![MatchingTransaction {
+                txid: format!("{:064x}", height),
+                tx_type: TransactionType::Burn,
+            }],
+            merkle_proofs: Vec::new(),
+            merkle_multiproof: None,
+            total_transactions: 2,
+            matching_count: 1,
+        }
+    }
+
+    /// Bundle around a dev-mode receipt committing to `journal` for image ID zero
+    pub fn bundle(journal: &BlockProofJournal, network: BitcoinNetwork) -> ProofBundle {
+        let words = risc0_zkvm::serde::to_vec(journal).unwrap();
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let receipt = Receipt::new(
+            InnerReceipt::Fake(FakeReceipt::new(ReceiptClaim::ok([0; 8], bytes.clone()))),
+            bytes,
+        );
+        let metadata = BundleMetadata {
+            created_at: 1_700_000_000,
+            host_version: "test".to_string(),
+            risc0_version: "test".to_string(),
+            prover: "local".to_string(),
+        };
+        ProofBundle::new(receipt, [0; 8], network, metadata).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::BlockHash;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_block_file() {
+        let raw_block = testing::block(BlockHash::all_zeros(), 0);
+        assert_eq!(decode_block_file(&raw_block).unwrap(), raw_block);
+
+        // Hex as written by `xxd -p` or an RPC dump, upper or lower case
+        let hex_file = format!("{}\n", hex::encode_upper(&raw_block));
+        assert_eq!(decode_block_file(hex_file.as_bytes()).unwrap(), raw_block);
+        assert!(decode_block_file(b"abc").is_err(), "odd-length hex");
+        assert!(decode_block_file(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sanity_check_block() {
+        let raw_block = testing::block(BlockHash::all_zeros(), 0);
+        sanity_check_block(&raw_block).unwrap();
+
+        let mut wrong_root = raw_block.clone();
+        wrong_root[36] ^= 1;
+        let error = sanity_check_block(&wrong_root).unwrap_err().to_string();
+        assert!(error.contains("Merkle root"), "{}", error);
+
+        let error = sanity_check_block(&raw_block[..raw_block.len() - 1])
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("Block does not parse"), "{}", error);
+    }
+
+    #[test]
+    fn test_memory_source() {
+        let mut source = MemorySource::new();
+        assert!(source.tip_height().is_err());
+
+        let hashes = testing::extend(&mut source, 0, 3, 0);
+        assert_eq!(source.tip_height().unwrap(), 2);
+        for (height, hash) in hashes.iter().enumerate() {
+            let (block_hash, raw_block) = source.block_at(height as u64).unwrap();
+            assert_eq!(&block_hash, hash);
+            assert_eq!(block_hash_of(&raw_block).unwrap(), block_hash);
+        }
+        let child = sanity_check_block(&source.raw_block(&hashes[2]).unwrap()).unwrap();
+        assert_eq!(child.header.prev_blockhash.to_string(), hashes[1]);
+
+        // A competing block replaces the height, the old one stays fetchable by hash
+        let replaced = testing::extend(&mut source, 2, 1, 1);
+        assert_eq!(source.block_hash(2).unwrap(), replaced[0]);
+        source.raw_block(&hashes[2]).unwrap();
+        assert!(source.block_hash(3).is_err());
+    }
+
+    #[test]
+    fn test_block_at_checks_hash() {
+        let mut source = MemorySource::new();
+        let hashes = testing::extend(&mut source, 0, 2, 0);
+        // Serve the block at height 1 under the hash of height 0
+        let wrong = source.by_hash[&hashes[1]].clone();
+        source.by_hash.insert(hashes[0].clone(), wrong);
+
+        let error = source.block_at(0).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "memory returned block {} when asked for {}",
+                hashes[1], hashes[0]
+            )
+        );
+        source.block_at(1).unwrap();
+    }
+
+    #[test]
+    fn test_local_dir_source() {
+        let mut memory = MemorySource::new();
+        let hashes = testing::extend(&mut memory, 0, 4, 0);
+        let dir = tempfile::tempdir().unwrap();
+        let raw = |height: usize| memory.raw_block(&hashes[height]).unwrap();
+        fs::write(dir.path().join("block_0.bin"), raw(0)).unwrap();
+        fs::write(dir.path().join("block_1.hex"), hex::encode(raw(1))).unwrap();
+        fs::write(dir.path().join("block_3.bin"), raw(3)).unwrap();
+        fs::write(dir.path().join("block_1.json"), "{}").unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a block").unwrap();
+        // Files are only read when requested, so a broken one doesn't stop the rest
+        fs::write(dir.path().join("block_2.bin"), b"garbage").unwrap();
+
+        let source = LocalDirSource::open(dir.path()).unwrap();
+        assert_eq!(source.tip_height().unwrap(), 3);
+        assert_eq!(source.block_at(1).unwrap(), (hashes[1].clone(), raw(1)));
+        // Found by searching the unread files, past the broken one
+        assert_eq!(source.raw_block(&hashes[3]).unwrap(), raw(3));
+        assert!(source.raw_block(&"00".repeat(32)).is_err());
+        assert!(source.block_hash(4).is_err());
+        let error = source.block_hash(2).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid block file"), "{}", error);
+    }
+
+    #[test]
+    fn test_esplora_block_hash_is_trimmed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            // Esplora ends plain-text bodies with a newline
+            let body = format!("{}\n", "00".repeat(32));
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let source = EsploraSource::new(&url);
+        assert_eq!(source.block_hash(1).unwrap(), "00".repeat(32));
+    }
+}
@@ -0,0 +1,639 @@
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use methods::types::BitcoinNetwork;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::block_source::BlockSource;
+use crate::daemon_state::{committed_block_hash, DaemonState, STATE_FILE};
+use crate::pipeline::{persist_stage, prescan_stage, prove_stage, Action, Item, Job, Restart};
+use crate::proof_store::ProofStore;
+use crate::reorg::{handle_reorg, proof_files, ProvenChain, MAX_REORG_DEPTH};
+use crate::{fetch_block, ChainConfig, OutputFormat, PrescanMode};
+
+/// Longest wait between retries of a failing block or tip query
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Settings for [`run_daemon`]
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    /// Where to start; `None` resumes from the saved [`DaemonState`]
+    pub start_height: Option<u64>,
+    pub output_dir: String,
+    /// Reprove heights that already have proof files instead of keeping them or stopping
+    pub overwrite: bool,
+    pub format: OutputFormat,
+    pub prescan: PrescanMode,
+    pub chain: ChainConfig,
+    /// Blocks proven in parallel
+    pub workers: usize,
+    /// Blocks fetched ahead of the workers
+    pub prefetch: usize,
+    /// Confirmations a block needs before it is proven (1 proves the tip)
+    pub confirmations: u64,
+    /// Prove at the tip anyway, and re-check each block once it has `confirmations`
+    pub fast_path: bool,
+    /// How often to ask the source for a new tip once caught up
+    pub poll_interval: Duration,
+    /// First wait after a failure, doubled on each consecutive failure
+    pub retry_delay: Duration,
+}
+
+/// Runs the daemon to continuously process new blocks
+///
+/// Proves every block up to the source's tip, then polls the tip and waits for new
+/// blocks instead of asking for heights that don't exist yet. Only real failures (a
+/// source error, a failed proof) are retried, with exponential backoff.
+///
+/// The hashes of processed blocks are tracked so a reorg is noticed either when the
+/// source's chain no longer contains the last one, or when a new block doesn't build
+/// on it. Orphaned proofs are then moved aside and the new branch is reproven.
+///
+/// Blocks are only proven once they have `confirmations`, so proofs aren't paid for
+/// blocks likely to be reorged out. With `fast_path` they are proven at the tip
+/// instead, and each one is checked against the source again when it gets deep enough.
+///
+/// Progress is saved to `daemon_state.json` after every block, and a restart without a
+/// start height resumes from it. Existing proof files are never replaced silently: a
+/// proof of the same block is kept, anything else stops the daemon unless `overwrite`.
+///
+/// Fetching, pre-scanning, proving (`workers` blocks at once) and persisting run as
+/// separate stages of an async pipeline, with up to `prefetch` fetched blocks waiting
+/// for a free worker; see [`crate::pipeline`].
+pub fn run_daemon(config: DaemonConfig, source: Arc<dyn BlockSource>) -> anyhow::Result<()> {
+    if config.confirmations == 0 {
+        return Err(anyhow::anyhow!("Confirmations must be at least 1"));
+    }
+    if config.workers == 0 {
+        return Err(anyhow::anyhow!("At least one worker is needed"));
+    }
+    if config.fast_path && config.confirmations > MAX_REORG_DEPTH as u64 {
+        return Err(anyhow::anyhow!(
+            "The fast path can re-check at most {} confirmations",
+            MAX_REORG_DEPTH
+        ));
+    }
+
+    println!("🚀 Starting Bitcoin ZK Proof Daemon");
+    println!("   Network: {}", config.chain.network);
+    println!("   Output directory: {}", config.output_dir);
+    println!("   Block source: {}", source.name());
+    println!(
+        "   Confirmations: {}{}",
+        config.confirmations,
+        if config.fast_path {
+            " (fast path: proving at tip)"
+        } else {
+            ""
+        }
+    );
+
+    // Create output directory
+    fs::create_dir_all(&config.output_dir)?;
+    let output_dir = Path::new(&config.output_dir);
+
+    let network = config.chain.network;
+    let saved = DaemonState::load(output_dir)?;
+    if let Some(saved) = saved.as_ref().filter(|saved| saved.network != network) {
+        return Err(anyhow::anyhow!(
+            "{} holds {} proofs, not {}",
+            config.output_dir,
+            saved.network,
+            network
+        ));
+    }
+    // Fast path: next_unconfirmed is the lowest proven block not yet re-checked at
+    // full depth
+    let (chain, current_height, next_unconfirmed) = match (config.start_height, saved) {
+        (None, Some(saved)) => {
+            println!(
+                "📂 Resuming after block {} ({})",
+                saved.last_height, saved.last_hash
+            );
+            (
+                saved.recent_blocks,
+                saved.last_height + 1,
+                saved.next_unconfirmed,
+            )
+        }
+        (Some(start_height), Some(mut saved)) => {
+            if start_height != saved.last_height + 1 {
+                println!(
+                    "⚠️  Starting at {} although the saved progress ends at block {}",
+                    start_height, saved.last_height
+                );
+            }
+            saved.recent_blocks.truncate(start_height);
+            (
+                saved.recent_blocks,
+                start_height,
+                saved.next_unconfirmed.min(start_height),
+            )
+        }
+        (Some(start_height), None) => (ProvenChain::new(), start_height, start_height),
+        (None, None) => {
+            return Err(anyhow::anyhow!(
+                "No {} in {}, pass --start-height for the first run",
+                STATE_FILE,
+                config.output_dir
+            ))
+        }
+    };
+    println!("   Starting height: {}", current_height);
+    println!(
+        "   Workers: {}, prefetching up to {} blocks",
+        config.workers, config.prefetch
+    );
+
+    let store = ProofStore::open(output_dir, network)?;
+
+    let config = Arc::new(config);
+    let progress = Arc::new(Progress {
+        committed: AtomicU64::new(current_height),
+        next_unconfirmed: AtomicU64::new(next_unconfirmed),
+    });
+    let (fetched_sender, fetched_receiver) = mpsc::channel(config.prefetch.max(1));
+    let (scanned_sender, scanned_receiver) = mpsc::channel(1);
+    let (finished_sender, finished_receiver) = mpsc::channel(config.workers);
+    let (restart_sender, restart_receiver) = mpsc::channel(1);
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let fetch = tokio::spawn(fetch_stage(
+            config.clone(),
+            source.clone(),
+            chain.clone(),
+            progress.clone(),
+            fetched_sender,
+            restart_receiver,
+        ));
+        let prescan = tokio::spawn(prescan_stage(
+            config.clone(),
+            fetched_receiver,
+            scanned_sender,
+        ));
+        let prove = tokio::spawn(prove_stage(
+            config.clone(),
+            scanned_receiver,
+            finished_sender,
+        ));
+        let persist = tokio::spawn(persist_stage(
+            config.clone(),
+            source.clone(),
+            chain,
+            store,
+            finished_receiver,
+            restart_sender,
+            progress,
+        ));
+
+        // The first stage to fail stops the daemon; the others wind down as their
+        // channels close, and proofs in flight are allowed to finish
+        tokio::try_join!(
+            joined(fetch),
+            joined(prescan),
+            joined(prove),
+            joined(persist)
+        )?;
+        Ok(())
+    })
+}
+
+/// Progress shared by the fetch and persist stages
+pub struct Progress {
+    /// Every block below this height is proven and committed
+    pub committed: AtomicU64,
+    /// Fast path: lowest proven block not yet re-checked at full depth
+    pub next_unconfirmed: AtomicU64,
+}
+
+/// Picks the next block once it is deep enough, fetches it ahead of the provers and
+/// watches the source for reorgs
+///
+/// Sending blocks down the pipeline waits while it is full, which bounds how far
+/// fetching runs ahead.
+async fn fetch_stage(
+    config: Arc<DaemonConfig>,
+    source: Arc<dyn BlockSource>,
+    mut chain: ProvenChain,
+    progress: Arc<Progress>,
+    jobs: Sender<Item<Job>>,
+    mut restarts: Receiver<Restart>,
+) -> anyhow::Result<()> {
+    let output_dir = Path::new(&config.output_dir);
+    let mut next_height = progress.committed.load(Ordering::SeqCst);
+    let mut retry = Backoff::new(config.retry_delay);
+    let mut waiting_at_tip = None;
+    let mut announce_tip = true;
+
+    loop {
+        let tip_height = match blocking(&source, |source| source.tip_height()).await {
+            Ok(tip_height) => tip_height,
+            Err(e) => {
+                println!("❌ Failed to query tip from {}: {}", source.name(), e);
+                retry.wait().await;
+                continue;
+            }
+        };
+
+        // Blocks already fetched must still be on the source's best chain
+        let consistent = {
+            let chain = chain.clone();
+            let progress = progress.clone();
+            let (confirmations, fast_path) = (config.confirmations, config.fast_path);
+            blocking(&source, move |source| {
+                let consistent = chain.tip_matches(source, tip_height)?;
+                if !consistent || !fast_path {
+                    return Ok(consistent);
+                }
+                let mut next_unconfirmed = progress.next_unconfirmed.load(Ordering::SeqCst);
+                let consistent = confirm_proven(
+                    &chain,
+                    source,
+                    tip_height,
+                    confirmations,
+                    &mut next_unconfirmed,
+                    progress.committed.load(Ordering::SeqCst),
+                );
+                progress
+                    .next_unconfirmed
+                    .store(next_unconfirmed, Ordering::SeqCst);
+                consistent
+            })
+            .await
+        };
+        match consistent {
+            Ok(true) => {}
+            Ok(false) => {
+                let Some(restart) = await_restart(&jobs, &mut restarts).await else {
+                    return Ok(());
+                };
+                chain = restart.chain;
+                next_height = restart.next_height;
+                waiting_at_tip = None;
+                announce_tip = true;
+                continue;
+            }
+            Err(e) => {
+                println!("❌ Failed to check the chain on {}: {}", source.name(), e);
+                retry.wait().await;
+                continue;
+            }
+        }
+
+        // Highest block deep enough to prove
+        let provable_height = if config.fast_path {
+            Some(tip_height)
+        } else {
+            (tip_height + 1).checked_sub(config.confirmations)
+        };
+
+        // Not mined (or not buried) yet: wait for the tip to move rather than treating
+        // it as an error
+        let Some(provable_height) = provable_height.filter(|height| next_height <= *height) else {
+            if waiting_at_tip != Some(tip_height) {
+                if config.confirmations > 1 && !config.fast_path {
+                    println!(
+                        "⏳ Caught up at tip {}, waiting for block {} to get {} confirmations",
+                        tip_height, next_height, config.confirmations
+                    );
+                } else {
+                    println!(
+                        "⏳ Caught up at tip {}, waiting for block {}",
+                        tip_height, next_height
+                    );
+                }
+                waiting_at_tip = Some(tip_height);
+                announce_tip = true;
+            }
+            tokio::time::sleep(config.poll_interval).await;
+            continue;
+        };
+        waiting_at_tip = None;
+        if announce_tip {
+            announce_tip = false;
+            println!(
+                "📡 Tip is {}, {} blocks to prove",
+                tip_height,
+                provable_height - next_height + 1
+            );
+        }
+
+        let fetched = {
+            let chain_config = config.chain.clone();
+            blocking(&source, move |source| {
+                fetch_block(next_height, &chain_config, source)
+            })
+            .await
+        };
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                println!("❌ Failed to fetch block {}: {}", next_height, e);
+                retry.wait().await;
+                continue;
+            }
+        };
+        let block_hash = fetched.block.block_hash().to_string();
+
+        // Each block must build on the one fetched before it
+        let previous = next_height
+            .checked_sub(1)
+            .and_then(|height| chain.hash_at(height));
+        if previous
+            .is_some_and(|previous| previous != fetched.block.header.prev_blockhash.to_string())
+        {
+            println!(
+                "🔀 Block {} does not build on block {}",
+                next_height,
+                next_height - 1
+            );
+            let Some(restart) = await_restart(&jobs, &mut restarts).await else {
+                return Ok(());
+            };
+            chain = restart.chain;
+            next_height = restart.next_height;
+            announce_tip = true;
+            continue;
+        }
+
+        // Never replace a proof silently: one of this very block is kept as it is
+        let existing = proof_files(output_dir, next_height);
+        let action = if existing.is_empty() || config.overwrite {
+            Action::Prove
+        } else if existing
+            .iter()
+            .all(|file| committed_block_hash(file).as_deref() == Some(block_hash.as_str()))
+        {
+            println!(
+                "♻️  Keeping the existing proof of block {} ({})",
+                next_height, block_hash
+            );
+            Action::Keep
+        } else {
+            return Err(anyhow::anyhow!(
+                "Proof files for height {} don't prove block {}: {}. Move them away or pass --overwrite",
+                next_height,
+                block_hash,
+                existing
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        };
+
+        chain.push(next_height, block_hash.clone());
+        retry.reset();
+        let job = Job {
+            fetched,
+            block_hash,
+            action,
+        };
+        // A closed pipeline means a later stage failed; it reports the error
+        if jobs.send(Item::Block(job)).await.is_err() {
+            return Ok(());
+        }
+        next_height += 1;
+    }
+}
+
+/// Sends a reorg marker down the pipeline and waits for the persist stage to recover
+///
+/// `None` if the pipeline stopped in the meantime.
+async fn await_restart(
+    jobs: &Sender<Item<Job>>,
+    restarts: &mut Receiver<Restart>,
+) -> Option<Restart> {
+    jobs.send(Item::Reorg).await.ok()?;
+    restarts.recv().await
+}
+
+/// Runs a block source call on tokio's blocking threads
+async fn blocking<T: Send + 'static>(
+    source: &Arc<dyn BlockSource>,
+    call: impl FnOnce(&dyn BlockSource) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    let source = source.clone();
+    tokio::task::spawn_blocking(move || call(&*source)).await?
+}
+
+/// Result of a stage task
+async fn joined(stage: JoinHandle<anyhow::Result<()>>) -> anyhow::Result<()> {
+    stage.await?
+}
+
+/// Re-checks fast-path proofs that reached the confirmation depth
+///
+/// Returns `false` as soon as one is no longer on the source's best chain.
+fn confirm_proven(
+    chain: &ProvenChain,
+    source: &dyn BlockSource,
+    tip_height: u64,
+    confirmations: u64,
+    next_unconfirmed: &mut u64,
+    proven_up_to: u64,
+) -> anyhow::Result<bool> {
+    while *next_unconfirmed < proven_up_to && *next_unconfirmed + confirmations <= tip_height + 1 {
+        if !chain.matches_at(source, tip_height, *next_unconfirmed)? {
+            return Ok(false);
+        }
+        println!(
+            "🔒 Block {} confirmed with {} confirmations",
+            next_unconfirmed, confirmations
+        );
+        *next_unconfirmed += 1;
+    }
+    Ok(true)
+}
+
+/// Saves the daemon's progress, once it has any
+pub fn save_state(
+    output_dir: &Path,
+    network: BitcoinNetwork,
+    chain: &ProvenChain,
+    next_unconfirmed: u64,
+) -> anyhow::Result<()> {
+    match DaemonState::capture(network, chain, next_unconfirmed) {
+        Some(state) => state.save(output_dir),
+        None => Ok(()),
+    }
+}
+
+/// Moves orphaned proofs aside and returns the height to resume proving at
+pub fn recover_from_reorg(
+    chain: &mut ProvenChain,
+    store: &mut ProofStore,
+    source: &dyn BlockSource,
+    tip_height: u64,
+    output_dir: &Path,
+) -> anyhow::Result<u64> {
+    let event = handle_reorg(chain, source, tip_height, output_dir)?;
+    for block in &event.orphaned {
+        store.orphan(block.height, &block.block_hash)?;
+    }
+    println!(
+        "🔀 Reorg: {} proven blocks orphaned above {} ({})",
+        event.orphaned.len(),
+        event.common_ancestor_height,
+        event.common_ancestor_hash
+    );
+    for block in &event.orphaned {
+        println!(
+            "   {} {} -> {}",
+            block.height,
+            block.block_hash,
+            block.replaced_by.as_deref().unwrap_or("(no block)")
+        );
+    }
+    Ok(event.common_ancestor_height + 1)
+}
+
+/// Exponential backoff between retries
+pub struct Backoff {
+    initial: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration) -> Self {
+        Self {
+            initial,
+            next: initial,
+        }
+    }
+
+    /// Sleeps for the current delay and doubles it, up to [`MAX_RETRY_DELAY`]
+    pub async fn wait(&mut self) {
+        println!("   Retrying in {}s", self.next.as_secs());
+        tokio::time::sleep(self.next).await;
+        self.next = (self.next * 2).min(MAX_RETRY_DELAY);
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_source::{testing, MemorySource};
+
+    use super::*;
+
+    const NETWORK: BitcoinNetwork = BitcoinNetwork::Regtest;
+
+    /// A source with blocks `0..count`, all proven on the fast path
+    fn proven(count: u64) -> (MemorySource, ProvenChain) {
+        let mut source = MemorySource::new();
+        let mut chain = ProvenChain::new();
+        for (height, block_hash) in testing::extend(&mut source, 0, count, 0)
+            .into_iter()
+            .enumerate()
+        {
+            chain.push(height as u64, block_hash);
+        }
+        (source, chain)
+    }
+
+    #[test]
+    fn test_confirm_proven_canonical_blocks() {
+        let (source, chain) = proven(10);
+
+        // With the tip at 9, blocks up to 7 have 3 confirmations
+        let mut next_unconfirmed = 5;
+        assert!(confirm_proven(&chain, &source, 9, 3, &mut next_unconfirmed, 10).unwrap());
+        assert_eq!(next_unconfirmed, 8);
+
+        // Nothing past what was proven is confirmed
+        let mut next_unconfirmed = 5;
+        assert!(confirm_proven(&chain, &source, 9, 3, &mut next_unconfirmed, 6).unwrap());
+        assert_eq!(next_unconfirmed, 6);
+    }
+
+    #[test]
+    fn test_confirm_proven_detects_reorged_block() {
+        let (mut source, chain) = proven(8);
+        // Block 7 was proven at the tip, then replaced before it had 3 confirmations
+        testing::fork(&mut source, 7, 2, 1);
+
+        // Too shallow to be checked yet
+        let mut next_unconfirmed = 5;
+        assert!(confirm_proven(&chain, &source, 8, 3, &mut next_unconfirmed, 8).unwrap());
+        assert_eq!(next_unconfirmed, 7);
+
+        // Once the new branch buries it, the stale proof is caught
+        testing::extend(&mut source, 9, 1, 1);
+        assert!(!confirm_proven(&chain, &source, 9, 3, &mut next_unconfirmed, 8).unwrap());
+        assert_eq!(next_unconfirmed, 7);
+    }
+
+    #[test]
+    fn test_resume_keeps_proofs_of_the_same_block() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir_path = output_dir.path();
+        let (source, chain) = proven(6);
+        let mut saved = chain.clone();
+        saved.truncate(4);
+        DaemonState::capture(NETWORK, &saved, 4)
+            .unwrap()
+            .save(output_dir_path)
+            .unwrap();
+
+        // Block 4 was proven before the restart, block 5's file is from another chain
+        let write_proof = |height: u64, block_hash: &str| {
+            let proof = testing::proof(height, block_hash, NETWORK);
+            fs::write(
+                output_dir_path.join(format!("block_{}.json", height)),
+                serde_json::to_string(&proof).unwrap(),
+            )
+            .unwrap();
+        };
+        write_proof(4, chain.hash_at(4).unwrap());
+        write_proof(5, &"ff".repeat(32));
+
+        let config = DaemonConfig {
+            start_height: None,
+            output_dir: output_dir_path.display().to_string(),
+            overwrite: false,
+            format: OutputFormat::Json,
+            prescan: PrescanMode::Skip,
+            chain: ChainConfig {
+                network: NETWORK,
+                signet_challenge: None,
+            },
+            workers: 1,
+            prefetch: 1,
+            confirmations: 1,
+            fast_path: false,
+            poll_interval: Duration::from_millis(10),
+            retry_delay: Duration::from_millis(10),
+        };
+        let kept = fs::read(output_dir_path.join("block_4.json")).unwrap();
+        let error = run_daemon(config, Arc::new(source))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with("Proof files for height 5 don't prove block"),
+            "{}",
+            error
+        );
+
+        // Resumed after block 3 and kept block 4 as it was. Block 5 is fetched ahead,
+        // so the daemon may stop before block 4 is committed.
+        let state = DaemonState::load(output_dir_path).unwrap().unwrap();
+        assert!((3..=4).contains(&state.last_height));
+        assert_eq!(
+            Some(state.last_hash.as_str()),
+            chain.hash_at(state.last_height)
+        );
+        assert_eq!(
+            fs::read(output_dir_path.join("block_4.json")).unwrap(),
+            kept
+        );
+        assert!(output_dir_path.join("block_5.json").exists());
+    }
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use methods::types::{BitcoinBlockProof, BitcoinNetwork, ProofBundle};
+use serde::{Deserialize, Serialize};
+
+use crate::reorg::ProvenChain;
+
+/// Daemon progress file in the output directory
+pub const STATE_FILE: &str = "daemon_state.json";
+
+/// Daemon progress, saved after every processed block so a restart resumes from it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonState {
+    pub network: BitcoinNetwork,
+    /// Last processed block
+    pub last_height: u64,
+    pub last_hash: String,
+    /// Fast path: lowest proven block not yet re-checked at full depth
+    pub next_unconfirmed: u64,
+    /// Hashes of recently processed blocks, so reorgs are still noticed after a restart
+    pub recent_blocks: ProvenChain,
+}
+
+impl DaemonState {
+    /// Snapshot of the daemon's progress, once it has processed a block
+    pub fn capture(
+        network: BitcoinNetwork,
+        chain: &ProvenChain,
+        next_unconfirmed: u64,
+    ) -> Option<Self> {
+        let (last_height, last_hash) = chain.last()?;
+        Some(Self {
+            network,
+            last_height,
+            last_hash: last_hash.to_string(),
+            next_unconfirmed,
+            recent_blocks: chain.clone(),
+        })
+    }
+
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STATE_FILE)
+    }
+
+    /// Loads the saved state, if the daemon has run in `output_dir` before
+    pub fn load(output_dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let state = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|e| anyhow::anyhow!("Invalid daemon state {}: {}", path.display(), e))?;
+        Ok(Some(state))
+    }
+
+    /// Writes the state to a temporary file and renames it over the old one, so a
+    /// crash never leaves a half-written state behind
+    pub fn save(&self, output_dir: &Path) -> anyhow::Result<()> {
+        let path = Self::path(output_dir);
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+}
+
+/// Block hash an existing proof file commits to, if it can be read
+pub fn committed_block_hash(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    match path.extension()?.to_str()? {
+        // Only the hash is needed, so proofs written by older versions still count
+        "json" => serde_json::from_slice::<serde_json::Value>(&data)
+            .ok()?
+            .get("block_hash")?
+            .as_str()
+            .map(str::to_string),
+        "cbor" => BitcoinBlockProof::from_cbor(&data)
+            .ok()
+            .map(|proof| proof.block_hash),
+        "bundle" => match ProofBundle::from_cbor(&data).ok()?.journal {
+            Ok(proof) => Some(proof.block_hash),
+            Err(failure) => failure.block_hash,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use methods::types::{BlockProofFailure, BlockProofJournal, ProofError};
+
+    use crate::block_source::{testing, BlockSource, MemorySource};
+
+    use super::*;
+
+    const NETWORK: BitcoinNetwork = BitcoinNetwork::Regtest;
+
+    /// CBOR bundle committing to `journal`
+    fn bundle(journal: &BlockProofJournal) -> Vec<u8> {
+        testing::bundle(journal, NETWORK).to_cbor().unwrap()
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut source = MemorySource::new();
+        let mut chain = ProvenChain::new();
+        for (height, block_hash) in testing::extend(&mut source, 0, 4, 0)
+            .into_iter()
+            .enumerate()
+        {
+            chain.push(height as u64, block_hash);
+        }
+
+        let state = DaemonState::capture(NETWORK, &chain, 2).unwrap();
+        state.save(output_dir.path()).unwrap();
+        assert!(!output_dir.path().join("daemon_state.json.tmp").exists());
+
+        let loaded = DaemonState::load(output_dir.path()).unwrap().unwrap();
+        assert_eq!(loaded.network, NETWORK);
+        assert_eq!(loaded.last_height, 3);
+        assert_eq!(loaded.last_hash, source.block_hash(3).unwrap());
+        assert_eq!(loaded.next_unconfirmed, 2);
+        for height in 0..4 {
+            assert_eq!(
+                loaded.recent_blocks.hash_at(height),
+                chain.hash_at(height),
+                "{}",
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn test_load_without_state() {
+        let output_dir = tempfile::tempdir().unwrap();
+        assert!(DaemonState::load(output_dir.path()).unwrap().is_none());
+        assert!(DaemonState::capture(NETWORK, &ProvenChain::new(), 0).is_none());
+
+        fs::write(DaemonState::path(output_dir.path()), "{\"network\": ").unwrap();
+        let error = DaemonState::load(output_dir.path()).unwrap_err();
+        assert!(
+            error.to_string().starts_with("Invalid daemon state"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_committed_block_hash() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let block_hash = "11".repeat(32);
+        let proof = testing::proof(7, &block_hash, NETWORK);
+        let failure = BlockProofFailure {
+            block_height: 8,
+            block_hash: Some("22".repeat(32)),
+            network_magic: NETWORK.magic(),
+            signet_challenge_hash: None,
+            strategy: proof.strategy.clone(),
+            error: ProofError::MerkleRootMismatch,
+        };
+
+        let files = [
+            (
+                "block_7.json",
+                serde_json::to_vec(&proof).unwrap(),
+                Some(block_hash.clone()),
+            ),
+            // Written before the journal had a network
+            (
+                "block_6.json",
+                format!("{{\"block_hash\": \"{}\"}}", block_hash).into_bytes(),
+                Some(block_hash.clone()),
+            ),
+            (
+                "block_7.cbor",
+                proof.to_cbor().unwrap(),
+                Some(block_hash.clone()),
+            ),
+            (
+                "block_7.bundle",
+                bundle(&Ok(proof.clone())),
+                Some(block_hash.clone()),
+            ),
+            // A committed rejection still names the block it rejected
+            (
+                "block_8.bundle",
+                bundle(&Err(failure)),
+                Some("22".repeat(32)),
+            ),
+            ("block_9.json", b"{\"block_hash\": ".to_vec(), None),
+            ("block_9.cbor", b"not cbor".to_vec(), None),
+            ("block_9.txt", block_hash.clone().into_bytes(), None),
+        ];
+        for (name, data, expected) in files {
+            let path = output_dir.join(name);
+            fs::write(&path, data).unwrap();
+            assert_eq!(committed_block_hash(&path), expected, "{}", name);
+        }
+        assert_eq!(
+            committed_block_hash(&output_dir.join("block_10.json")),
+            None
+        );
+    }
+}
@@ -1,11 +1,37 @@
-use clap::{Parser, Subcommand};
-use risc0_zkvm::{default_prover, ExecutorEnv};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use risc0_zkvm::{default_executor, default_prover, ExecutorEnv, SessionInfo};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // These constants represent the RISC-V ELF and the image ID generated by risc0-build.
 use methods::{BITCOIN_PROOFS_ELF, BITCOIN_PROOFS_ID};
 
+mod bench;
+mod bitcoin_rpc;
+mod blk_files;
+mod block_source;
+mod daemon;
+mod daemon_state;
+mod pipeline;
+mod prescan;
+mod proof_store;
+mod reorg;
+mod serve;
+
+use bench::{run_bench, BenchCase, BenchReport};
+use bitcoin_rpc::{BitcoinRpcClient, RpcAuth};
+use blk_files::BlkFilesSource;
+use block_source::{
+    decode_block_file, sanity_check_block, BlockSource, EsploraSource, LocalDirSource, MemorySource,
+};
+use daemon::{run_daemon, DaemonConfig};
+use prescan::{prescan, PreScan};
+use proof_store::ProofStore;
+use serve::{run_server, ServeConfig};
+
 /// Bitcoin ZK Proof Daemon - Generates zero-knowledge proofs for Bitcoin block processing
 #[derive(Parser)]
 #[command(name = "bitcoin-zk-proofs")]
@@ -22,42 +48,324 @@ enum Commands {
         /// Block height to process
         #[arg(long)]
         height: u64,
-        /// Output file for the proof
+        /// Prove this block file (raw or hex) instead of fetching; --height is taken as given
+        #[arg(long)]
+        block_file: Option<String>,
+        /// Output file for the proof (the extension follows --format)
         #[arg(short, long, default_value = "proof.json")]
         output: String,
         /// Strategy: "searching" or "pointing:txid:position:type"
         #[arg(long, default_value = "searching")]
         strategy: String,
+        /// Proof file format
+        #[arg(long, value_enum, default_value = "json")]
+        format: OutputFormat,
+        /// What to do with the native pre-scan before proving
+        #[arg(long, value_enum, default_value = "report")]
+        prescan: PrescanMode,
+        #[command(flatten)]
+        source: SourceArgs,
+    },
+    /// Run the guest on a block without proving, reporting cycles, segments and logs
+    Execute {
+        /// Block height to process
+        #[arg(long)]
+        height: u64,
+        /// Execute this block file (raw or hex) instead of fetching
+        #[arg(long)]
+        block_file: Option<String>,
+        /// Strategy: "searching" or "pointing:txid:position:type"
+        #[arg(long, default_value = "searching")]
+        strategy: String,
+        #[command(flatten)]
+        source: SourceArgs,
+    },
+    /// Execute (and optionally prove) fixture blocks and report cycles, size and time
+    Bench {
+        /// Proof fixtures to benchmark (defaults to test_*.json in the current directory)
+        fixtures: Vec<String>,
+        /// Raw or hex block files named block_<height>.bin / .hex
+        #[arg(long)]
+        block_file: Vec<String>,
+        /// Also prove each block
+        #[arg(long)]
+        prove: bool,
+        /// Where to write the JSON report
+        #[arg(short, long, default_value = "bench_report.json")]
+        output: String,
+        /// Previous report to compare against
+        #[arg(long)]
+        baseline: Option<String>,
+        /// Fail if cycles, segments or proof size grow by more than this percentage
+        #[arg(long, requires = "baseline")]
+        max_regression: Option<f64>,
+        #[command(flatten)]
+        source: SourceArgs,
     },
     /// Verify a ZK proof
     Verify {
-        /// Proof file to verify
+        /// Proof file to verify (JSON or CBOR, detected automatically)
         #[arg(short, long)]
         proof_file: String,
+        /// Proof bundle file (defaults to the proof file with a .bundle extension)
+        #[arg(short, long)]
+        bundle_file: Option<String>,
+        /// Also check the proven block hash against the block source's chain
+        #[arg(long)]
+        check_chain: bool,
+        #[command(flatten)]
+        source: SourceArgs,
     },
     /// Run as a daemon, continuously processing new blocks
     Daemon {
-        /// Starting block height
-        #[arg(short, long, default_value = "0")]
-        start_height: u64,
+        /// Starting block height (default: resume from the output directory's saved state)
+        #[arg(short, long)]
+        start_height: Option<u64>,
         /// Output directory for proofs
         #[arg(short, long, default_value = "./proofs")]
         output_dir: String,
+        /// Reprove heights that already have proof files instead of keeping them or stopping
+        #[arg(long)]
+        overwrite: bool,
+        /// Proof file format
+        #[arg(long, value_enum, default_value = "json")]
+        format: OutputFormat,
+        /// What to do with the native pre-scan before proving
+        #[arg(long, value_enum, default_value = "report")]
+        prescan: PrescanMode,
+        /// Blocks proven in parallel. Local proving needs memory per worker; with
+        /// Bonsai this is the number of concurrent remote sessions
+        #[arg(long, default_value = "1")]
+        workers: usize,
+        /// Blocks fetched ahead of the workers while they prove
+        #[arg(long, default_value = "1")]
+        prefetch: usize,
+        /// Confirmations a block needs before it is proven (1 proves the tip)
+        #[arg(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+        confirmations: u64,
+        /// Prove at the tip regardless of --confirmations, and re-check each block
+        /// against the source once it has that many
+        #[arg(long)]
+        fast_path: bool,
+        /// Seconds between tip checks once caught up
+        #[arg(long, default_value = "30")]
+        poll_interval: u64,
+        /// Seconds before the first retry of a failed block (doubles up to 5 minutes)
+        #[arg(long, default_value = "10")]
+        retry_delay: u64,
+        #[command(flatten)]
+        source: SourceArgs,
     },
+    /// Serve proofs from an output directory over HTTP
+    Serve {
+        /// Directory the daemon writes proofs to
+        #[arg(short, long, default_value = "./proofs")]
+        output_dir: String,
+        /// Bitcoin network of the proofs
+        #[arg(long, default_value = "mainnet")]
+        network: BitcoinNetwork,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Find which stored proofs commit to a transaction
+    FindTx {
+        /// Transaction ID (as shown by explorers)
+        txid: String,
+        /// Directory holding the proof store
+        #[arg(short, long, default_value = "./proofs")]
+        output_dir: String,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Import proof files into the output directory's proof store
+    Import {
+        /// Directory holding block_<height>.* proof files
+        #[arg(short, long, default_value = "./proofs")]
+        output_dir: String,
+        /// Bitcoin network of the proofs
+        #[arg(long, default_value = "mainnet")]
+        network: BitcoinNetwork,
+    },
+}
+
+/// How the native pre-scan gates proving
+///
+/// The pre-scan runs the guest's matching code natively on the fetched block, so it
+/// knows in milliseconds what a proof would commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PrescanMode {
+    /// Print the pre-scan and always prove
+    Report,
+    /// Don't prove blocks with no matches or a pointing target the guest would reject
+    Skip,
+    /// Print the pre-scan without proving
+    Only,
 }
 
-/// Input data for the ZK proof
+/// Where blocks are fetched from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SourceKind {
+    /// Blockstream's public Esplora API
+    Esplora,
+    /// A Bitcoin Core node over JSON-RPC
+    Rpc,
+    /// A local directory of `block_<height>.bin` / `.hex` files
+    Dir,
+    /// Bitcoin Core's `blk*.dat` files, read directly
+    Blk,
+}
+
+/// Network settings the guest checks blocks against
+#[derive(Debug, Clone)]
+struct ChainConfig {
+    network: BitcoinNetwork,
+    signet_challenge: Option<Vec<u8>>,
+}
+
+/// Challenge of the public signet (Bitcoin Core's default `-signetchallenge`)
+const DEFAULT_SIGNET_CHALLENGE: &str = "512103ad5e0edad18cb1f0fc0d28a3d4f1f3e445640337489abb10404f2d1e086be430210359ef5021964fe22d6f8e05b2463c9540ce96883fe3b278760f048f5189f2e6c452ae";
+
+/// Network and block source options shared by `prove`, `daemon` and `verify`
+#[derive(Debug, Clone, Args)]
+struct SourceArgs {
+    /// Bitcoin network: mainnet, testnet4, signet or regtest
+    #[arg(long, default_value = "mainnet")]
+    network: BitcoinNetwork,
+    /// Signet challenge script as hex (defaults to the public signet's)
+    #[arg(long)]
+    signet_challenge: Option<String>,
+    /// Block source
+    #[arg(long, value_enum, default_value = "esplora")]
+    source: SourceKind,
+    /// Esplora API base URL (defaults to a public instance for the network)
+    #[arg(long)]
+    esplora_url: Option<String>,
+    /// Directory of raw blocks for `--source dir`
+    #[arg(long, default_value = "./blocks")]
+    blocks_dir: String,
+    /// Bitcoin Core `blocks/` directory for `--source blk` (e.g. ~/.bitcoin/blocks)
+    #[arg(long, required_if_eq("source", "blk"))]
+    blk_dir: Option<String>,
+    /// Bitcoin Core RPC URL (defaults to localhost on the network's RPC port)
+    #[arg(long)]
+    rpc_url: Option<String>,
+    /// Bitcoin Core cookie file (used instead of user/password when set)
+    #[arg(long)]
+    rpc_cookie: Option<String>,
+    /// Bitcoin Core RPC user
+    #[arg(long, env = "BITCOIN_RPC_USER", requires = "rpc_password")]
+    rpc_user: Option<String>,
+    /// Bitcoin Core RPC password
+    #[arg(long, env = "BITCOIN_RPC_PASSWORD", hide_env_values = true)]
+    rpc_password: Option<String>,
+}
+
+impl SourceArgs {
+    /// Opens the selected block source
+    fn open(&self) -> anyhow::Result<Box<dyn BlockSource>> {
+        let source: Box<dyn BlockSource> = match self.source {
+            SourceKind::Esplora => Box::new(EsploraSource::new(&self.esplora_url()?)),
+            SourceKind::Rpc => Box::new(self.rpc_client()),
+            SourceKind::Dir => Box::new(LocalDirSource::open(&self.blocks_dir)?),
+            SourceKind::Blk => {
+                let blk_dir = self.blk_dir.as_deref().unwrap_or_default();
+                println!("📂 Indexing blk*.dat files in {}", blk_dir);
+                let source = BlkFilesSource::open(blk_dir, self.network.magic())?;
+                println!("✅ Indexed chain up to height {}", source.tip_height()?);
+                Box::new(source)
+            }
+        };
+        Ok(source)
+    }
+
+    /// Network settings for the guest, with the BIP325 challenge resolved on signet
+    fn chain(&self) -> anyhow::Result<ChainConfig> {
+        let signet_challenge = match (self.network, &self.signet_challenge) {
+            (BitcoinNetwork::Signet, Some(challenge)) => Some(hex::decode(challenge)?),
+            (BitcoinNetwork::Signet, None) => Some(hex::decode(DEFAULT_SIGNET_CHALLENGE)?),
+            (_, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "--signet-challenge only applies to --network signet"
+                ))
+            }
+            (_, None) => None,
+        };
+        Ok(ChainConfig {
+            network: self.network,
+            signet_challenge,
+        })
+    }
+
+    /// Esplora URL, falling back to the public instance for the network
+    fn esplora_url(&self) -> anyhow::Result<String> {
+        if let Some(url) = &self.esplora_url {
+            return Ok(url.clone());
+        }
+        match self.network {
+            BitcoinNetwork::Mainnet => Ok("https://blockstream.info/api".to_string()),
+            BitcoinNetwork::Testnet4 => Ok("https://mempool.space/testnet4/api".to_string()),
+            BitcoinNetwork::Signet => Ok("https://mempool.space/signet/api".to_string()),
+            BitcoinNetwork::Regtest => Err(anyhow::anyhow!(
+                "There is no public Esplora for regtest, pass --esplora-url or use --source rpc"
+            )),
+        }
+    }
+
+    /// Bitcoin Core RPC client for these options
+    fn rpc_client(&self) -> BitcoinRpcClient {
+        let auth = match (&self.rpc_cookie, &self.rpc_user, &self.rpc_password) {
+            (Some(cookie), _, _) => RpcAuth::Cookie(cookie.clone()),
+            // clap rejects --rpc-user without --rpc-password
+            (None, Some(user), Some(password)) => RpcAuth::UserPass {
+                user: user.clone(),
+                password: password.clone(),
+            },
+            _ => RpcAuth::None,
+        };
+        let url = self.rpc_url.clone().unwrap_or_else(|| {
+            let port = match self.network {
+                BitcoinNetwork::Mainnet => 8332,
+                BitcoinNetwork::Testnet4 => 48332,
+                BitcoinNetwork::Signet => 38332,
+                BitcoinNetwork::Regtest => 18443,
+            };
+            format!("http://127.0.0.1:{}", port)
+        });
+        BitcoinRpcClient::new(&url, auth)
+    }
+}
+
+/// Proof file formats written by `prove` and `daemon`
+///
+/// Files share a stem and differ by extension: `<stem>.json`, `<stem>.cbor`, plus the
+/// `<stem>.bundle` written alongside. The daemon uses the `block_<height>` stem, which is
+/// the layout Core Lane's proof cache reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Pretty-printed JSON
+    Json,
+    /// CBOR (`BitcoinBlockProof::to_cbor`)
+    Cbor,
+    /// Both JSON and CBOR
+    Both,
+}
+
+/// Input data for the ZK proof (the raw block is streamed separately)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct BitcoinBlockInput {
-    pub raw_block: Vec<u8>,
     pub block_height: u64,
+    pub network: BitcoinNetwork,
+    pub signet_challenge: Option<Vec<u8>>,
     pub strategy: ProofStrategy,
 }
 
 /// Output data from the ZK proof (re-exported from methods)
 use methods::types::{
-    BitcoinBlockProof, PointingProof, ProofStrategy, SearchingProof, TransactionPattern,
-    TransactionType,
+    BitcoinBlockProof, BitcoinNetwork, BlockProofFailure, BlockProofJournal, BundleMetadata,
+    MerkleProof, PointingProof, ProofBundle, ProofError, ProofStrategy, SearchingProof,
+    TransactionPattern, TransactionType,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,53 +383,447 @@ fn main() -> anyhow::Result<()> {
 
     let cli = Cli::parse();
 
-    match cli.command {
+    let result = match cli.command {
         Commands::Prove {
             height,
+            block_file,
             output,
             strategy,
+            format,
+            prescan,
+            source,
         } => {
-            generate_proof(height, &output, &strategy)?;
+            let chain = source.chain()?;
+            match block_file {
+                Some(block_file) => block_file_source(height, &block_file).and_then(|source| {
+                    generate_proof(height, &output, &strategy, format, prescan, &chain, &source)
+                }),
+                None => source.open().and_then(|source| {
+                    generate_proof(
+                        height, &output, &strategy, format, prescan, &chain, &*source,
+                    )
+                }),
+            }
+        }
+        Commands::Execute {
+            height,
+            block_file,
+            strategy,
+            source,
+        } => {
+            let chain = source.chain()?;
+            match block_file {
+                Some(block_file) => block_file_source(height, &block_file)
+                    .and_then(|source| execute_block(height, &strategy, &chain, &source)),
+                None => source
+                    .open()
+                    .and_then(|source| execute_block(height, &strategy, &chain, &*source)),
+            }
         }
-        Commands::Verify { proof_file } => {
-            verify_proof(&proof_file)?;
+        Commands::Bench {
+            fixtures,
+            block_file,
+            prove,
+            output,
+            baseline,
+            max_regression,
+            source,
+        } => run_bench_command(
+            fixtures,
+            &block_file,
+            prove,
+            &output,
+            baseline.as_deref(),
+            max_regression,
+            &source,
+        ),
+        Commands::Verify {
+            proof_file,
+            bundle_file,
+            check_chain,
+            source,
+        } => {
+            let chain = if check_chain {
+                Some(source.open()?)
+            } else {
+                None
+            };
+            verify_proof(
+                &proof_file,
+                bundle_file.as_deref(),
+                source.network,
+                chain.as_deref(),
+            )
         }
         Commands::Daemon {
             start_height,
             output_dir,
+            overwrite,
+            format,
+            prescan,
+            source,
+            workers,
+            prefetch,
+            confirmations,
+            fast_path,
+            poll_interval,
+            retry_delay,
         } => {
-            run_daemon(start_height, &output_dir)?;
+            let config = DaemonConfig {
+                start_height,
+                output_dir,
+                overwrite,
+                format,
+                prescan,
+                chain: source.chain()?,
+                workers,
+                prefetch,
+                confirmations,
+                fast_path,
+                poll_interval: Duration::from_secs(poll_interval),
+                retry_delay: Duration::from_secs(retry_delay),
+            };
+            source
+                .open()
+                .and_then(|block_source| run_daemon(config, Arc::from(block_source)))
         }
+        Commands::Serve {
+            output_dir,
+            network,
+            listen,
+        } => run_server(ServeConfig {
+            output_dir,
+            network,
+            listen,
+        }),
+        Commands::Import {
+            output_dir,
+            network,
+        } => import_proofs(Path::new(&output_dir), network),
+        Commands::FindTx {
+            txid,
+            output_dir,
+            json,
+        } => find_tx(Path::new(&output_dir), &txid, json),
+    };
+
+    // Blocks the guest provably rejected get a dedicated exit code per reason
+    if let Err(e) = &result {
+        if let Some(failure) = e.downcast_ref::<BlockProofFailure>() {
+            std::process::exit(failure_exit_code(&failure.error));
+        }
+    }
+
+    result
+}
+
+/// Exit code for each reason the guest can reject a block
+fn failure_exit_code(error: &ProofError) -> i32 {
+    match error {
+        ProofError::MalformedBlock(_) => 10,
+        ProofError::MerkleRootMismatch => 11,
+        ProofError::InvalidStrategy(_) => 12,
+        ProofError::TransactionNotFound { .. } => 13,
+        ProofError::NotCoreLaneTransaction { .. } => 14,
+        ProofError::TypeMismatch { .. } => 15,
+        ProofError::MerkleProofFailed => 16,
+        ProofError::InvalidProofOfWork => 17,
+        ProofError::InvalidSignetSolution(_) => 18,
+    }
+}
+
+/// Source holding only the block in `block_file` (raw or hex) at `height`, for offline
+/// proving
+fn block_file_source(height: u64, block_file: &str) -> anyhow::Result<MemorySource> {
+    let raw_block = decode_block_file(&fs::read(block_file)?)
+        .map_err(|e| anyhow::anyhow!("Failed to read block file {}: {}", block_file, e))?;
+    let mut source = MemorySource::new();
+    source.insert(height, raw_block)?;
+    Ok(source)
+}
+
+/// A block fetched and sanity-checked on the host, ready for the guest
+struct FetchedBlock {
+    height: u64,
+    raw_block: Vec<u8>,
+    block: bitcoin::Block,
+}
+
+/// Fetches a block and sanity-checks it
+fn fetch_block(
+    block_height: u64,
+    chain: &ChainConfig,
+    source: &dyn BlockSource,
+) -> anyhow::Result<FetchedBlock> {
+    println!(
+        "🔍 Fetching {} block at height {} from {}",
+        chain.network,
+        block_height,
+        source.name()
+    );
+
+    // Fetch the block from the selected source
+    let (block_hash, raw_block) = source.block_at(block_height)?;
+    println!(
+        "✅ Fetched block {} ({} bytes)",
+        block_hash,
+        raw_block.len()
+    );
+
+    // Catch truncated or corrupt blocks here rather than as a guest rejection
+    let block = sanity_check_block(&raw_block)?;
+    println!(
+        "✅ Block parses: {} transactions, Merkle root matches header",
+        block.txdata.len()
+    );
+
+    Ok(FetchedBlock {
+        height: block_height,
+        raw_block,
+        block,
+    })
+}
+
+/// Runs the guest without proving and reports its journal, cost and logs
+///
+/// Execution is deterministic, so the journal is exactly what a proof would commit and
+/// the segment count is what proving would have to cover.
+fn execute_block(
+    block_height: u64,
+    strategy_str: &str,
+    chain: &ChainConfig,
+    source: &dyn BlockSource,
+) -> anyhow::Result<()> {
+    let fetched = fetch_block(block_height, chain, source)?;
+
+    let strategy = parse_strategy(strategy_str)?;
+    println!("Using strategy: {:?}", strategy);
+    print_prescan(
+        &prescan(&fetched.block, &strategy, chain.network),
+        &strategy,
+    );
+
+    let input = BitcoinBlockInput {
+        block_height,
+        network: chain.network,
+        signet_challenge: chain.signet_challenge.clone(),
+        strategy,
+    };
+
+    println!("⚙️  Executing guest (no proof)...");
+
+    // `env::log` output arrives on the guest's stdout
+    let mut guest_log = Vec::new();
+    let env = ExecutorEnv::builder()
+        .write(&input)
+        .unwrap()
+        .write_slice(&fetched.raw_block)
+        .stdout(&mut guest_log)
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    let session = default_executor().execute(env, BITCOIN_PROOFS_ELF)?;
+    let elapsed = started.elapsed();
+
+    let total_cycles = padded_cycles(&session);
+    println!("✅ Execution finished in {:.2?}", elapsed);
+    println!("   User cycles: {}", session.cycles());
+    println!(
+        "   Total cycles: {} (padded to segment sizes)",
+        total_cycles
+    );
+    println!("   Segments: {}", session.segments.len());
+
+    println!("📜 Guest log:");
+    for line in String::from_utf8_lossy(&guest_log).lines() {
+        println!("   {}", line);
+    }
+
+    let journal: BlockProofJournal = session.journal.decode()?;
+    println!("🧾 Journal:");
+    println!("{}", serde_json::to_string_pretty(&journal)?);
+
+    if let Err(failure) = journal {
+        println!(
+            "❌ Guest rejected block {}: {}",
+            failure.block_height, failure.error
+        );
+        return Err(failure.into());
     }
 
     Ok(())
 }
 
-/// Generates a ZK proof for a specific Bitcoin block
-fn generate_proof(block_height: u64, output_file: &str, strategy_str: &str) -> anyhow::Result<()> {
-    println!("🔍 Fetching Bitcoin block at height {}", block_height);
+/// Cycles after padding each segment to its power of two, which is what proving pays for
+fn padded_cycles(session: &SessionInfo) -> u64 {
+    session
+        .segments
+        .iter()
+        .map(|segment| 1u64 << segment.po2)
+        .sum()
+}
 
-    // Fetch the block from Blockstream API
-    let raw_block = fetch_bitcoin_block(block_height)?;
-    println!("✅ Fetched block ({} bytes)", raw_block.len());
+/// Runs `bench` and writes its report, failing on regressions past `max_regression`
+fn run_bench_command(
+    fixtures: Vec<String>,
+    block_files: &[String],
+    prove: bool,
+    output: &str,
+    baseline: Option<&str>,
+    max_regression: Option<f64>,
+    source_args: &SourceArgs,
+) -> anyhow::Result<()> {
+    // Without explicit inputs, benchmark the saved test_*.json proofs
+    let fixtures = if fixtures.is_empty() && block_files.is_empty() {
+        let mut found: Vec<String> = fs::read_dir(".")?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with("test_") && name.ends_with(".json"))
+            .collect();
+        found.sort();
+        found
+    } else {
+        fixtures
+    };
+
+    let mut cases = Vec::new();
+    for fixture in &fixtures {
+        cases.push(BenchCase::from_fixture(fixture)?);
+    }
+    for block_file in block_files {
+        cases.push(BenchCase::from_block_file(block_file)?);
+    }
+    if cases.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to benchmark"));
+    }
+    println!("📊 Benchmarking {} blocks", cases.len());
+
+    let chain = source_args.chain()?;
+    let source = if cases.iter().any(|case| case.raw_block.is_none()) {
+        Some(source_args.open()?)
+    } else {
+        None
+    };
+    let mut report = run_bench(&cases, prove, &chain, source.as_deref())?;
+
+    if let Some(baseline) = baseline {
+        let baseline: BenchReport = serde_json::from_slice(&fs::read(baseline)?)?;
+        if baseline.image_id != report.image_id {
+            println!("⚠️  Baseline was produced by a different guest image");
+        }
+        report.compare(&baseline);
+        for delta in report
+            .deltas
+            .iter()
+            .filter(|delta| delta.baseline != delta.current)
+        {
+            println!(
+                "   {} {}: {} -> {} ({:+.1}%)",
+                delta.name, delta.metric, delta.baseline, delta.current, delta.change_percent
+            );
+        }
+    }
+
+    fs::write(output, serde_json::to_string_pretty(&report)?)?;
+    println!("💾 Bench report saved to {}", output);
+
+    if let Some(max_regression) = max_regression {
+        let regressions = report.regressions(max_regression);
+        if !regressions.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} metrics regressed by more than {}% against the baseline",
+                regressions.len(),
+                max_regression
+            ));
+        }
+    }
+    let mismatched = report
+        .entries
+        .iter()
+        .filter(|entry| !entry.fixture_mismatches.is_empty())
+        .count();
+    if mismatched > 0 {
+        return Err(anyhow::anyhow!(
+            "{} blocks no longer match their fixtures",
+            mismatched
+        ));
+    }
+
+    Ok(())
+}
+
+/// Generates a ZK proof for a specific Bitcoin block
+fn generate_proof(
+    block_height: u64,
+    output_file: &str,
+    strategy_str: &str,
+    format: OutputFormat,
+    prescan_mode: PrescanMode,
+    chain: &ChainConfig,
+    source: &dyn BlockSource,
+) -> anyhow::Result<()> {
+    let fetched = fetch_block(block_height, chain, source)?;
 
     // Parse strategy
     let strategy = parse_strategy(strategy_str)?;
     println!("Using strategy: {:?}", strategy);
 
+    if !plan_proof(&fetched.block, &strategy, prescan_mode, chain.network)? {
+        return Ok(());
+    }
+    let bundle = prove_fetched(&fetched, strategy, chain)?;
+    persist_proof(&bundle, output_file, format)
+}
+
+/// Predicts the journal natively and decides whether the block is worth proving
+fn plan_proof(
+    block: &bitcoin::Block,
+    strategy: &ProofStrategy,
+    prescan_mode: PrescanMode,
+    network: BitcoinNetwork,
+) -> anyhow::Result<bool> {
+    // Predict the journal natively before spending minutes proving
+    let scan = prescan(block, strategy, network);
+    print_prescan(&scan, strategy);
+    match prescan_mode {
+        PrescanMode::Report => Ok(true),
+        PrescanMode::Only => Ok(false),
+        PrescanMode::Skip => {
+            if let Some(rejection) = scan.rejection {
+                return Err(anyhow::anyhow!("Pre-scan: {}, not proving", rejection));
+            }
+            if scan.is_empty() {
+                println!("⏭️  No matching transactions, skipping proof");
+                return Ok(false);
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Proves an already fetched block and verifies the receipt
+fn prove_fetched(
+    fetched: &FetchedBlock,
+    strategy: ProofStrategy,
+    chain: &ChainConfig,
+) -> anyhow::Result<ProofBundle> {
     // Create input for the ZK proof
     let input = BitcoinBlockInput {
-        raw_block,
-        block_height,
+        block_height: fetched.height,
+        network: chain.network,
+        signet_challenge: chain.signet_challenge.clone(),
         strategy,
     };
 
-    println!("🔐 Generating ZK proof...");
+    println!("🔐 Generating ZK proof for block {}...", fetched.height);
 
-    // Create executor environment with the block data
+    // Create executor environment: the input struct, then the raw block bytes
+    // which the guest decodes as a stream
     let env = ExecutorEnv::builder()
         .write(&input)
         .unwrap()
+        .write_slice(&fetched.raw_block)
         .build()
         .unwrap();
 
@@ -131,84 +833,413 @@ fn generate_proof(block_height: u64, output_file: &str, strategy_str: &str) -> a
     let prove_info = prover.prove(env, BITCOIN_PROOFS_ELF)?;
     let receipt = prove_info.receipt;
 
+    // Bundle the receipt with its decoded journal and metadata
+    let bundle = ProofBundle::new(receipt, BITCOIN_PROOFS_ID, chain.network, bundle_metadata())
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    // Verify the proof before anything is written
+    bundle
+        .verify(BITCOIN_PROOFS_ID)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    println!(
+        "✅ Proof verification successful for block {}!",
+        fetched.height
+    );
+
+    Ok(bundle)
+}
+
+/// Saves a proof bundle and, if the guest accepted the block, the proof files
+///
+/// A committed rejection is saved as a bundle too, and returned as the error.
+fn persist_proof(
+    bundle: &ProofBundle,
+    output_file: &str,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    // The bundle goes next to the proof
+    let bundle_file = bundle_path(output_file);
+    bundle.write(&bundle_file).map_err(|e| anyhow::anyhow!(e))?;
+    println!(
+        "💾 Proof bundle saved to {} ({:?} receipt)",
+        bundle_file.display(),
+        bundle.receipt_kind
+    );
+
     // Extract the proof result
-    let proof: BitcoinBlockProof = receipt.journal.decode()?;
+    let proof = match &bundle.journal {
+        Ok(proof) => proof,
+        Err(failure) => {
+            // The rejection is committed, so it is as trustworthy as a proof
+            print_failure(failure);
+            return Err(failure.clone().into());
+        }
+    };
 
     println!("✅ ZK proof generated successfully!");
     println!("   Block Hash: {}", proof.block_hash);
     println!("   Total Transactions: {}", proof.total_transactions);
     println!("   Matching Transactions: {}", proof.matching_count);
 
-    // Save the proof to file
-    let proof_data = serde_json::to_string_pretty(&proof)?;
-    fs::write(output_file, proof_data)?;
-    println!("💾 Proof saved to {}", output_file);
-
-    // Verify the proof
-    receipt.verify(BITCOIN_PROOFS_ID)?;
-    println!("✅ Proof verification successful!");
+    // Save the proof in the requested formats
+    write_proof(proof, output_file, format)?;
 
     Ok(())
 }
 
-/// Verifies a ZK proof from file
-fn verify_proof(proof_file: &str) -> anyhow::Result<()> {
+/// Prints what the pre-scan expects the guest to commit
+fn print_prescan(scan: &PreScan, strategy: &ProofStrategy) {
+    println!(
+        "⚡ Pre-scan: {} matching transactions out of {}",
+        scan.matches.len(),
+        scan.total_transactions
+    );
+    for found in &scan.matches {
+        println!("   #{} {} ({:?})", found.index, found.txid, found.tx_type);
+    }
+    if let Some(rejection) = &scan.rejection {
+        println!("⚠️  The guest will reject this block: {}", rejection);
+    }
+    if let (ProofStrategy::Pointing(pointing_proof), Some(found)) = (strategy, scan.matches.first())
+    {
+        if found.index != pointing_proof.tx_position {
+            println!(
+                "⚠️  Target is at position {}, not {} as given",
+                found.index, pointing_proof.tx_position
+            );
+        }
+    }
+}
+
+/// Creation metadata recorded in every proof bundle
+fn bundle_metadata() -> BundleMetadata {
+    // Mirrors how `default_prover` picks a backend
+    let prover = std::env::var("RISC0_PROVER").unwrap_or_else(|_| {
+        if std::env::var("BONSAI_API_KEY").is_ok() {
+            "bonsai".to_string()
+        } else {
+            "local".to_string()
+        }
+    });
+
+    BundleMetadata {
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+        host_version: env!("CARGO_PKG_VERSION").to_string(),
+        risc0_version: risc0_zkvm::VERSION.to_string(),
+        prover,
+    }
+}
+
+/// Prints the diagnostics for a block the guest rejected
+fn print_failure(failure: &BlockProofFailure) {
+    println!("❌ Guest rejected block {}", failure.block_height);
+    if let Some(block_hash) = &failure.block_hash {
+        println!("   Block Hash: {}", block_hash);
+    }
+    println!("   Strategy: {:?}", failure.strategy);
+    println!("   Reason: {}", failure.error);
+    println!(
+        "   Exit code: {} (receipt verified, rejection is provable)",
+        failure_exit_code(&failure.error)
+    );
+}
+
+/// Verifies a ZK proof from file against its proof bundle
+///
+/// The bundle's receipt must verify against `BITCOIN_PROOFS_ID`, the proof must be
+/// for `network` and its journal must match the proof file field for field. With a
+/// block source, the proven block must also be the one the source has at that height.
+fn verify_proof(
+    proof_file: &str,
+    bundle_file: Option<&str>,
+    network: BitcoinNetwork,
+    chain: Option<&dyn BlockSource>,
+) -> anyhow::Result<()> {
     println!("🔍 Verifying proof from {}", proof_file);
 
     // Read the proof file
-    let proof_data = fs::read_to_string(proof_file)?;
-    let _proof: BitcoinBlockProof = serde_json::from_str(&proof_data)?;
+    let proof = read_proof(proof_file)?;
 
-    // TODO: Implement full proof verification
-    // For now, we'll just verify the file can be parsed
-    println!("✅ Proof file is valid JSON");
+    // Read and verify the bundle
+    let bundle_file = match bundle_file {
+        Some(path) => PathBuf::from(path),
+        None => bundle_path(proof_file),
+    };
+    println!("🔍 Loading proof bundle from {}", bundle_file.display());
+    let bundle = ProofBundle::read(&bundle_file).map_err(|e| anyhow::anyhow!(e))?;
+    bundle
+        .verify(BITCOIN_PROOFS_ID)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    println!(
+        "✅ {:?} receipt verified against image ID (network: {}, format v{})",
+        bundle.receipt_kind, bundle.network, bundle.format_version
+    );
+    if bundle.network != network {
+        return Err(anyhow::anyhow!(
+            "Proof is for {}, not {}",
+            bundle.network,
+            network
+        ));
+    }
+
+    // Compare the committed journal to the proof file
+    let committed = match &bundle.journal {
+        Ok(committed) => committed,
+        Err(failure) => {
+            print_failure(failure);
+            return Err(anyhow::anyhow!(
+                "Receipt commits a rejection, but {} contains a proof",
+                proof_file
+            ));
+        }
+    };
+
+    let mismatches = mismatched_fields(&proof, committed)?;
+    if !mismatches.is_empty() {
+        for field in &mismatches {
+            println!("❌ Field `{}` does not match the receipt journal", field);
+        }
+        return Err(anyhow::anyhow!(
+            "Proof file does not match the receipt journal ({} fields differ)",
+            mismatches.len()
+        ));
+    }
+
+    println!("✅ Proof file matches the receipt journal");
+
+    if let Some(source) = chain {
+        let canonical_hash = source.block_hash(committed.block_height)?;
+        if canonical_hash != committed.block_hash {
+            return Err(anyhow::anyhow!(
+                "Proven block {} is not the block at height {} on {} ({})",
+                committed.block_hash,
+                committed.block_height,
+                source.name(),
+                canonical_hash
+            ));
+        }
+        println!("✅ Block hash matches {}", source.name());
+    }
+    println!("   Block Hash: {}", committed.block_hash);
+    println!("   Block Height: {}", committed.block_height);
+    println!("   Matching Transactions: {}", committed.matching_count);
+
+    Ok(())
+}
+
+/// Lists the top-level fields that differ between two proofs
+fn mismatched_fields(
+    proof: &BitcoinBlockProof,
+    committed: &BitcoinBlockProof,
+) -> anyhow::Result<Vec<String>> {
+    let proof = serde_json::to_value(proof)?;
+    let committed = serde_json::to_value(committed)?;
+    let (Some(proof), Some(committed)) = (proof.as_object(), committed.as_object()) else {
+        return Err(anyhow::anyhow!("Proofs must serialize to JSON objects"));
+    };
+
+    Ok(committed
+        .iter()
+        .filter(|(field, value)| proof.get(*field) != Some(*value))
+        .map(|(field, _)| field.clone())
+        .collect())
+}
+
+/// Writes a proof in the selected formats next to each other
+fn write_proof(
+    proof: &BitcoinBlockProof,
+    output_file: &str,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    if matches!(format, OutputFormat::Json | OutputFormat::Both) {
+        let path = Path::new(output_file).with_extension("json");
+        fs::write(&path, serde_json::to_string_pretty(proof)?)?;
+        println!("💾 Proof saved to {}", path.display());
+    }
+    if matches!(format, OutputFormat::Cbor | OutputFormat::Both) {
+        let path = Path::new(output_file).with_extension("cbor");
+        fs::write(&path, proof.to_cbor().map_err(|e| anyhow::anyhow!(e))?)?;
+        println!("💾 Proof saved to {}", path.display());
+    }
+    Ok(())
+}
 
+/// Imports the proof files in `output_dir` into its proof store
+///
+/// Heights already in the store are left alone, so this can be rerun after copying
+/// in more files.
+fn import_proofs(output_dir: &Path, network: BitcoinNetwork) -> anyhow::Result<()> {
+    println!("📦 Importing proofs from {}", output_dir.display());
+    let (mut store, _) = ProofStore::connect(output_dir)?;
+    let summary = store.import_dir(output_dir, network)?;
+    println!(
+        "✅ Imported {} blocks into {} ({} already stored, {} unreadable)",
+        summary.imported,
+        ProofStore::path(output_dir).display(),
+        summary.already_stored,
+        summary.unreadable
+    );
     Ok(())
 }
 
-/// Runs the daemon to continuously process new blocks
-fn run_daemon(start_height: u64, output_dir: &str) -> anyhow::Result<()> {
-    println!("🚀 Starting Bitcoin ZK Proof Daemon");
-    println!("   Starting height: {}", start_height);
-    println!("   Output directory: {}", output_dir);
+/// A stored proof committing to a transaction, as printed by `find-tx --json`
+#[derive(Debug, Serialize)]
+struct TxLocation {
+    txid: String,
+    block_height: u64,
+    block_hash: String,
+    tx_type: String,
+    strategy: ProofStrategy,
+    /// Path from the transaction to the block's Merkle root, extracted from the
+    /// multiproof for searching proofs
+    merkle_proof: Option<MerkleProof>,
+    /// `None` if the proof was imported without its bundle
+    receipt_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receipt_error: Option<String>,
+}
 
-    // Create output directory
-    fs::create_dir_all(output_dir)?;
+/// Prints the stored proofs that commit to `txid`, checking each one's receipt
+///
+/// Fails if no proof has the transaction or a receipt doesn't verify.
+fn find_tx(output_dir: &Path, txid: &str, json: bool) -> anyhow::Result<()> {
+    let path = ProofStore::path(output_dir);
+    if !path.exists() {
+        return Err(anyhow::anyhow!(
+            "No proof store at {}, run `host import` first",
+            path.display()
+        ));
+    }
+    let (store, _) = ProofStore::connect(output_dir)?;
+    let txid = txid.to_lowercase();
 
-    let mut current_height = start_height;
+    let mut locations = Vec::new();
+    for (height, tx_type) in store.transaction_blocks(&txid)? {
+        // Only proofs (not rejections) have matching transactions
+        let proof = store
+            .proof_cbor(height)?
+            .ok_or_else(|| anyhow::anyhow!("Block {} has no stored proof", height))?;
+        let proof = BitcoinBlockProof::from_cbor(&proof).map_err(|e| anyhow::anyhow!(e))?;
+        let (receipt_verified, receipt_error) = match store.bundle_cbor(height)? {
+            Some(bundle) => match ProofBundle::from_cbor(&bundle)
+                .and_then(|bundle| bundle.verify(BITCOIN_PROOFS_ID))
+            {
+                Ok(()) => (Some(true), None),
+                Err(e) => (Some(false), Some(e)),
+            },
+            None => (None, None),
+        };
+        let merkle_proof = match &proof.merkle_multiproof {
+            // Searching proofs share one multiproof among all matches
+            Some(multiproof) => {
+                let leaves = proof.matching_txids().map_err(|e| anyhow::anyhow!(e))?;
+                match leaves.iter().find(|leaf| display_hex(leaf) == txid) {
+                    Some(leaf) => multiproof
+                        .proof_for(&leaves, leaf)
+                        .map_err(|e| anyhow::anyhow!(e))?,
+                    None => None,
+                }
+            }
+            None => proof
+                .merkle_proofs
+                .into_iter()
+                .find(|merkle_proof| display_hex(&merkle_proof.txid) == txid),
+        };
+        locations.push(TxLocation {
+            txid: txid.clone(),
+            block_height: height,
+            block_hash: proof.block_hash,
+            tx_type,
+            strategy: proof.strategy,
+            merkle_proof,
+            receipt_verified,
+            receipt_error,
+        });
+    }
 
-    loop {
-        match generate_proof(
-            current_height,
-            &format!("{}/block_{}.json", output_dir, current_height),
-            "searching",
-        ) {
-            Ok(_) => {
-                println!("✅ Processed block {}", current_height);
-                current_height += 1;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&locations)?);
+    } else {
+        println!("🔎 Transaction {}", txid);
+        for location in &locations {
+            println!(
+                "📦 Block {} ({})",
+                location.block_height, location.block_hash
+            );
+            println!("   Type: {}", location.tx_type);
+            println!("   Strategy: {:?}", location.strategy);
+            match &location.merkle_proof {
+                Some(merkle_proof) => {
+                    println!("   Merkle proof: {} levels", merkle_proof.path.len());
+                    for (sibling, is_right) in merkle_proof.path.iter().zip(&merkle_proof.positions)
+                    {
+                        let side = if *is_right { "right" } else { "left" };
+                        println!("      {:5} {}", side, display_hex(sibling));
+                    }
+                }
+                None => println!("   Merkle proof: none (proved before Merkle multiproofs)"),
             }
-            Err(e) => {
-                println!("❌ Error processing block {}: {}", current_height, e);
-                // Wait before retrying
-                std::thread::sleep(std::time::Duration::from_secs(10));
+            match (location.receipt_verified, &location.receipt_error) {
+                (Some(true), _) => println!("   ✅ Receipt verified against image ID"),
+                (Some(false), error) => println!(
+                    "   ❌ Receipt does not verify: {}",
+                    error.as_deref().unwrap_or_default()
+                ),
+                (None, _) => println!("   ⚠️  No receipt stored (imported without a bundle)"),
             }
         }
     }
+
+    if locations.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Transaction {} is not in any stored proof",
+            txid
+        ));
+    }
+    if let Some(failed) = locations
+        .iter()
+        .find(|location| location.receipt_verified == Some(false))
+    {
+        return Err(anyhow::anyhow!(
+            "Receipt of block {} does not verify",
+            failed.block_height
+        ));
+    }
+    Ok(())
+}
+
+/// Hash in the byte order explorers display (reversed from the internal order)
+fn display_hex(hash: &[u8; 32]) -> String {
+    let mut bytes = *hash;
+    bytes.reverse();
+    hex::encode(bytes)
 }
 
-/// Fetches a Bitcoin block from Blockstream API
-fn fetch_bitcoin_block(height: u64) -> anyhow::Result<Vec<u8>> {
-    // Get block hash
-    let hash_url = format!("https://blockstream.info/api/block-height/{}", height);
-    let response = reqwest::blocking::get(&hash_url)?;
-    let block_hash: String = response.text()?;
+/// Reads a proof file, detecting whether it holds JSON or CBOR
+fn read_proof(proof_file: &str) -> anyhow::Result<BitcoinBlockProof> {
+    let data = fs::read(proof_file)?;
 
-    // Get raw block data
-    let block_url = format!("https://blockstream.info/api/block/{}/raw", block_hash);
-    let response = reqwest::blocking::get(&block_url)?;
-    let block_data = response.bytes()?;
+    // A JSON proof is an object; CBOR maps never start with '{' (a 0x7b text string)
+    let is_json = data
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'{');
 
-    Ok(block_data.to_vec())
+    if is_json {
+        println!("   Detected JSON proof");
+        Ok(serde_json::from_slice(&data)?)
+    } else {
+        println!("   Detected CBOR proof");
+        BitcoinBlockProof::from_cbor(&data).map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+/// Proof bundle stored next to a proof file
+fn bundle_path(proof_file: &str) -> PathBuf {
+    Path::new(proof_file).with_extension("bundle")
 }
 
 /// Parse strategy string into ProofStrategy enum
@@ -247,3 +1278,244 @@ fn parse_strategy(strategy_str: &str) -> anyhow::Result<ProofStrategy> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use methods::types::{BlockProofJournal, MatchingTransaction};
+    use risc0_zkvm::{FakeReceipt, InnerReceipt, Journal, Receipt, ReceiptClaim};
+
+    fn rejection(error: ProofError) -> BlockProofFailure {
+        BlockProofFailure {
+            block_height: 916201,
+            block_hash: Some(
+                "00000000000000000000f07f586abf62cb55629a79da2c34d19e782d40189b64".to_string(),
+            ),
+            network_magic: BitcoinNetwork::Mainnet.magic(),
+            signet_challenge_hash: None,
+            strategy: ProofStrategy::Pointing(PointingProof {
+                txid: "69c4106b6c0d9ec67b7a0cfa54aed07f202ce99fdabf40e721000f2d4b71ae86"
+                    .to_string(),
+                tx_position: 0,
+                expected_type: TransactionType::Burn,
+            }),
+            error,
+        }
+    }
+
+    fn proof() -> BitcoinBlockProof {
+        BitcoinBlockProof {
+            block_hash: "00000000000000000001d95d5c3b832c7a78262500f9c5057b889b0f35702c9b"
+                .to_string(),
+            block_height: 916202,
+            network_magic: BitcoinNetwork::Mainnet.magic(),
+            signet_challenge_hash: None,
+            strategy: ProofStrategy::Searching(SearchingProof {
+                pattern: TransactionPattern::All,
+            }),
+            matching_transactions: vec![MatchingTransaction {
+                txid: "ab".repeat(32),
+                tx_type: TransactionType::Burn,
+            }],
+            merkle_proofs: Vec::new(),
+            merkle_multiproof: None,
+            total_transactions: 3362,
+            matching_count: 1,
+        }
+    }
+
+    #[test]
+    fn test_mismatched_fields_of_matching_proofs() {
+        assert!(mismatched_fields(&proof(), &proof()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mismatched_fields_of_diverging_proofs() {
+        let mut tampered = proof();
+        tampered.block_height += 1;
+        tampered.matching_transactions[0].tx_type = TransactionType::Fill;
+
+        let mut fields = mismatched_fields(&tampered, &proof()).unwrap();
+        fields.sort();
+        assert_eq!(fields, ["block_height", "matching_transactions"]);
+    }
+
+    #[test]
+    fn test_read_proof_detects_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("block_916202.json");
+        let cbor_file = dir.path().join("block_916202.cbor");
+        let json = serde_json::to_string_pretty(&proof()).unwrap();
+        fs::write(&json_file, format!("\n  {}", json)).unwrap();
+        fs::write(&cbor_file, proof().to_cbor().unwrap()).unwrap();
+
+        for file in [json_file, cbor_file] {
+            let read = read_proof(file.to_str().unwrap()).unwrap();
+            assert!(mismatched_fields(&read, &proof()).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_proof_rejects_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, data) in [
+            ("binary", &b"\xff\x00 not a proof"[..]),
+            ("truncated_json", &b"{\"block_hash\": "[..]),
+            ("empty", &b""[..]),
+        ] {
+            let file = dir.path().join(name);
+            fs::write(&file, data).unwrap();
+            assert!(read_proof(file.to_str().unwrap()).is_err(), "{}", name);
+        }
+        assert!(read_proof(dir.path().join("missing").to_str().unwrap()).is_err());
+    }
+
+    /// Dev-mode bundle committing to `proof` for `image_id`
+    fn dev_bundle(
+        proof: &BitcoinBlockProof,
+        network: BitcoinNetwork,
+        image_id: [u32; 8],
+    ) -> ProofBundle {
+        // Fake receipts only verify in dev mode
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        let journal: BlockProofJournal = Ok(proof.clone());
+        let words = risc0_zkvm::serde::to_vec(&journal).unwrap();
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let receipt = Receipt::new(
+            InnerReceipt::Fake(FakeReceipt::new(ReceiptClaim::ok(image_id, bytes.clone()))),
+            bytes,
+        );
+        ProofBundle::new(receipt, image_id, network, bundle_metadata()).unwrap()
+    }
+
+    /// Writes `proof` and a dev-mode bundle committing to it for `network`
+    fn write_proof_and_bundle(dir: &Path, network: BitcoinNetwork) -> String {
+        let mut proof = proof();
+        proof.network_magic = network.magic();
+        let bundle = dev_bundle(&proof, network, BITCOIN_PROOFS_ID);
+
+        let proof_file = dir.join("block_916202.json");
+        fs::write(&proof_file, serde_json::to_string_pretty(&proof).unwrap()).unwrap();
+        bundle.write(dir.join("block_916202.bundle")).unwrap();
+        proof_file.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_verify_proof_checks_network() {
+        let dir = tempfile::tempdir().unwrap();
+        let proof_file = write_proof_and_bundle(dir.path(), BitcoinNetwork::Testnet4);
+
+        verify_proof(&proof_file, None, BitcoinNetwork::Testnet4, None).unwrap();
+        // Without --check-chain, as with it
+        let error = verify_proof(&proof_file, None, BitcoinNetwork::Mainnet, None).unwrap_err();
+        assert_eq!(error.to_string(), "Proof is for testnet4, not mainnet");
+    }
+
+    #[test]
+    fn test_find_tx_with_one_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = ProofStore::open(dir.path(), BitcoinNetwork::Mainnet).unwrap();
+        let bundle = dev_bundle(&proof(), BitcoinNetwork::Mainnet, BITCOIN_PROOFS_ID);
+        store.put(&bundle).unwrap();
+
+        // Looked up whatever the case of the txid
+        find_tx(dir.path(), &"AB".repeat(32), true).unwrap();
+    }
+
+    #[test]
+    fn test_find_tx_without_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = find_tx(dir.path(), &"ab".repeat(32), false).unwrap_err();
+        assert!(error.to_string().starts_with("No proof store at"));
+
+        let mut store = ProofStore::open(dir.path(), BitcoinNetwork::Mainnet).unwrap();
+        let bundle = dev_bundle(&proof(), BitcoinNetwork::Mainnet, BITCOIN_PROOFS_ID);
+        store.put(&bundle).unwrap();
+
+        let txid = "cd".repeat(32);
+        let error = find_tx(dir.path(), &txid, false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Transaction {} is not in any stored proof", txid)
+        );
+    }
+
+    #[test]
+    fn test_find_tx_with_unverified_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = ProofStore::open(dir.path(), BitcoinNetwork::Mainnet).unwrap();
+        let bundle = dev_bundle(&proof(), BitcoinNetwork::Mainnet, BITCOIN_PROOFS_ID);
+        store.put(&bundle).unwrap();
+        // The same transaction in a later block, proven by another guest
+        let mut other = proof();
+        other.block_height += 1;
+        other.block_hash = "cd".repeat(32);
+        let mut other_id = BITCOIN_PROOFS_ID;
+        other_id[0] ^= 1;
+        store
+            .put(&dev_bundle(&other, BitcoinNetwork::Mainnet, other_id))
+            .unwrap();
+
+        let error = find_tx(dir.path(), &"ab".repeat(32), false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Receipt of block {} does not verify", other.block_height)
+        );
+    }
+
+    #[test]
+    fn test_failure_exit_codes() {
+        let txid = || "ab".repeat(32);
+        let codes = [
+            (ProofError::MalformedBlock("eof".to_string()), 10),
+            (ProofError::MerkleRootMismatch, 11),
+            (ProofError::InvalidStrategy("bad txid".to_string()), 12),
+            (ProofError::TransactionNotFound { txid: txid() }, 13),
+            (ProofError::NotCoreLaneTransaction { txid: txid() }, 14),
+            (
+                ProofError::TypeMismatch {
+                    txid: txid(),
+                    expected: TransactionType::Burn,
+                    actual: TransactionType::DataAvailability,
+                },
+                15,
+            ),
+            (ProofError::MerkleProofFailed, 16),
+            (ProofError::InvalidProofOfWork, 17),
+            (
+                ProofError::InvalidSignetSolution("empty witness".to_string()),
+                18,
+            ),
+        ];
+        for (error, code) in codes {
+            assert_eq!(failure_exit_code(&error), code, "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_rejection_journal_round_trip() {
+        let journal: BlockProofJournal = Err(rejection(ProofError::TypeMismatch {
+            txid: "69c4106b6c0d9ec67b7a0cfa54aed07f202ce99fdabf40e721000f2d4b71ae86".to_string(),
+            expected: TransactionType::Burn,
+            actual: TransactionType::DataAvailability,
+        }));
+
+        // Encoded as the guest commits it, decoded as the host reads receipts
+        let words = risc0_zkvm::serde::to_vec(&journal).unwrap();
+        let bytes = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let decoded: BlockProofJournal = Journal::new(bytes).decode().unwrap();
+
+        let failure = decoded.unwrap_err();
+        assert_eq!(failure.block_height, 916201);
+        assert_eq!(
+            failure.block_hash.as_deref(),
+            Some("00000000000000000000f07f586abf62cb55629a79da2c34d19e782d40189b64")
+        );
+        assert_eq!(failure.error, journal.as_ref().unwrap_err().error);
+
+        // Surfaced through anyhow, the rejection still maps to its exit code
+        let error = anyhow::Error::from(failure);
+        let failure = error.downcast_ref::<BlockProofFailure>().unwrap();
+        assert_eq!(failure_exit_code(&failure.error), 15);
+    }
+}
@@ -0,0 +1,589 @@
+//! Daemon stages after fetching: pre-scan, prove and persist
+//!
+//! Stages run as tokio tasks connected by bounded channels, so a slow stage holds back
+//! the ones before it instead of letting work pile up, and fetching continues while
+//! blocks are being proven. Blocking work (proving, block sources) runs on tokio's
+//! blocking threads.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use methods::types::{
+    BlockProofFailure, ProofBundle, ProofStrategy, SearchingProof, TransactionPattern,
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinSet;
+
+use crate::block_source::BlockSource;
+use crate::daemon::{recover_from_reorg, save_state, Backoff, DaemonConfig, Progress};
+use crate::proof_store::ProofStore;
+use crate::reorg::{orphan_proof_files, ProvenChain};
+use crate::{persist_proof, plan_proof, prove_fetched, FetchedBlock};
+
+/// What flows between stages
+pub enum Item<T> {
+    Block(T),
+    /// The fetch stage found a reorg. Each stage passes it on once everything before
+    /// it has left the stage, so the persist stage sees it after the last stale block.
+    Reorg,
+}
+
+/// A fetched block on its way to the prover
+pub struct Job {
+    pub fetched: FetchedBlock,
+    pub block_hash: String,
+    pub action: Action,
+}
+
+pub enum Action {
+    Prove,
+    /// Not worth proving according to the pre-scan
+    Skip,
+    /// Already proven on disk
+    Keep,
+}
+
+/// A block leaving the prove stage
+pub struct Finished {
+    pub height: u64,
+    pub block_hash: String,
+    pub outcome: Outcome,
+}
+
+pub enum Outcome {
+    Proven(Box<ProofBundle>),
+    Skipped,
+    Kept,
+    /// Proving failed after a reorg was found, so it wasn't retried
+    Abandoned,
+}
+
+/// Where to continue after a reorg, sent back to the fetch stage
+pub struct Restart {
+    pub next_height: u64,
+    pub chain: ProvenChain,
+}
+
+/// Runs the pre-scan on each block and drops the ones not worth proving
+pub async fn prescan_stage(
+    config: Arc<DaemonConfig>,
+    mut fetched: Receiver<Item<Job>>,
+    scanned: Sender<Item<Job>>,
+) -> anyhow::Result<()> {
+    let strategy = daemon_strategy();
+    while let Some(item) = fetched.recv().await {
+        let item = match item {
+            Item::Block(mut job) => {
+                if matches!(job.action, Action::Prove) {
+                    let prove = plan_proof(
+                        &job.fetched.block,
+                        &strategy,
+                        config.prescan,
+                        config.chain.network,
+                    )
+                    .unwrap_or_else(|e| {
+                        println!("⚠️  Block {}: {}", job.fetched.height, e);
+                        false
+                    });
+                    if !prove {
+                        job.action = Action::Skip;
+                    }
+                }
+                Item::Block(job)
+            }
+            Item::Reorg => Item::Reorg,
+        };
+        if scanned.send(item).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Proves up to `workers` blocks at once, retrying failures with backoff
+pub async fn prove_stage(
+    config: Arc<DaemonConfig>,
+    mut scanned: Receiver<Item<Job>>,
+    finished: Sender<Item<Finished>>,
+) -> anyhow::Result<()> {
+    let mut proving = JoinSet::new();
+    let mut retry = Backoff::new(config.retry_delay);
+    let mut input_closed = false;
+
+    loop {
+        tokio::select! {
+            // Only take a block when a worker is free, so the queue before this stage
+            // holds the prefetched blocks
+            item = scanned.recv(), if !input_closed && proving.len() < config.workers => {
+                match item {
+                    None => input_closed = true,
+                    Some(Item::Block(job)) => match job.action {
+                        Action::Prove => spawn_proof(&mut proving, &config, job),
+                        Action::Skip => finished.send(Item::Block(finish(job, Outcome::Skipped))).await?,
+                        Action::Keep => finished.send(Item::Block(finish(job, Outcome::Kept))).await?,
+                    },
+                    Some(Item::Reorg) => {
+                        // Stale or not, proofs in flight finish before files are moved
+                        while let Some(joined) = proving.join_next().await {
+                            let (job, result) = joined?;
+                            let outcome = match result {
+                                Ok(bundle) => Outcome::Proven(Box::new(bundle)),
+                                Err(e) => {
+                                    println!("❌ Error processing block {}: {}", job.fetched.height, e);
+                                    Outcome::Abandoned
+                                }
+                            };
+                            finished.send(Item::Block(finish(job, outcome))).await?;
+                        }
+                        finished.send(Item::Reorg).await?;
+                    }
+                }
+            }
+            Some(joined) = proving.join_next() => {
+                let (job, result) = joined?;
+                match result {
+                    Ok(bundle) => {
+                        retry.reset();
+                        finished
+                            .send(Item::Block(finish(job, Outcome::Proven(Box::new(bundle)))))
+                            .await?;
+                    }
+                    Err(e) => {
+                        println!("❌ Error processing block {}: {}", job.fetched.height, e);
+                        retry.wait().await;
+                        spawn_proof(&mut proving, &config, job);
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+    Ok(())
+}
+
+/// Writes proofs as they arrive and commits them in height order
+///
+/// A proof is committed (recorded in the chain and the saved state) only once every
+/// lower height is, so the saved height is always the last contiguous proven one.
+/// Proofs above a gap are already on disk, and kept on a restart. Writing a proof is
+/// retried with backoff until it succeeds, so a block is never committed without one,
+/// and the store only indexes what is on disk.
+pub async fn persist_stage(
+    config: Arc<DaemonConfig>,
+    source: Arc<dyn BlockSource>,
+    mut chain: ProvenChain,
+    mut store: ProofStore,
+    mut finished: Receiver<Item<Finished>>,
+    restarts: Sender<Restart>,
+    progress: Arc<Progress>,
+) -> anyhow::Result<()> {
+    let output_dir = Path::new(&config.output_dir);
+    let network = config.chain.network;
+    let mut pending = BTreeMap::new();
+    let mut retry = Backoff::new(config.retry_delay);
+
+    while let Some(item) = finished.recv().await {
+        match item {
+            Item::Block(done) => {
+                let output_file = format!("{}/block_{}.json", config.output_dir, done.height);
+                match &done.outcome {
+                    Outcome::Proven(bundle) => {
+                        loop {
+                            match persist_proof(bundle, &output_file, config.format) {
+                                Ok(()) => {
+                                    store.put(bundle)?;
+                                    retry.reset();
+                                    break;
+                                }
+                                // A committed rejection is final: its bundle is the record,
+                                // written before the rejection was reported, so it is
+                                // indexed like a proof
+                                Err(e) if e.downcast_ref::<BlockProofFailure>().is_some() => {
+                                    println!("⚠️  Block {} is not proven: {}", done.height, e);
+                                    store.put(bundle)?;
+                                    retry.reset();
+                                    break;
+                                }
+                                // Anything else (a full disk, say) must be neither indexed
+                                // nor committed, and the proof is too costly to throw away
+                                Err(e) => {
+                                    println!("❌ Failed to save block {}: {}", done.height, e);
+                                    retry.wait().await;
+                                }
+                            }
+                        }
+                    }
+                    // Written before a crash, possibly without reaching the store
+                    Outcome::Kept if !store.contains(done.height)? => {
+                        store.import_height(output_dir, done.height, network)?;
+                    }
+                    Outcome::Abandoned => continue,
+                    Outcome::Skipped | Outcome::Kept => {}
+                }
+                pending.insert(done.height, done);
+
+                let mut next_height = progress.committed.load(Ordering::SeqCst);
+                while let Some(done) = pending.remove(&next_height) {
+                    chain.push(done.height, done.block_hash);
+                    next_height = done.height + 1;
+                    progress.committed.store(next_height, Ordering::SeqCst);
+                    save_state(
+                        output_dir,
+                        network,
+                        &chain,
+                        progress.next_unconfirmed.load(Ordering::SeqCst),
+                    )?;
+                    println!("✅ Processed block {}", done.height);
+                }
+            }
+            Item::Reorg => {
+                // Left over above a gap, so never committed
+                for (height, done) in std::mem::take(&mut pending) {
+                    store.orphan(height, &done.block_hash)?;
+                    if !orphan_proof_files(output_dir, height, &done.block_hash)?.is_empty() {
+                        println!(
+                            "🔀 Set aside the uncommitted proof of block {} ({})",
+                            height, done.block_hash
+                        );
+                    }
+                }
+
+                let next_height = loop {
+                    let source = source.clone();
+                    let config = config.clone();
+                    let (returned, result) = tokio::task::spawn_blocking(move || {
+                        let result = source.tip_height().and_then(|tip_height| {
+                            recover_from_reorg(
+                                &mut chain,
+                                &mut store,
+                                &*source,
+                                tip_height,
+                                Path::new(&config.output_dir),
+                            )
+                        });
+                        ((chain, store), result)
+                    })
+                    .await?;
+                    (chain, store) = returned;
+                    match result {
+                        Ok(next_height) => break next_height,
+                        Err(e) => {
+                            println!("❌ Failed to recover from reorg: {}", e);
+                            retry.wait().await;
+                        }
+                    }
+                };
+                retry.reset();
+
+                progress.committed.store(next_height, Ordering::SeqCst);
+                progress
+                    .next_unconfirmed
+                    .fetch_min(next_height, Ordering::SeqCst);
+                save_state(
+                    output_dir,
+                    network,
+                    &chain,
+                    progress.next_unconfirmed.load(Ordering::SeqCst),
+                )?;
+                let restart = Restart {
+                    next_height,
+                    chain: chain.clone(),
+                };
+                if restarts.send(restart).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The daemon proves every Core Lane transaction
+fn daemon_strategy() -> ProofStrategy {
+    ProofStrategy::Searching(SearchingProof {
+        pattern: TransactionPattern::All,
+    })
+}
+
+fn spawn_proof(
+    proving: &mut JoinSet<(Job, anyhow::Result<ProofBundle>)>,
+    config: &Arc<DaemonConfig>,
+    job: Job,
+) {
+    let config = config.clone();
+    proving.spawn_blocking(move || {
+        let result = prove_fetched(&job.fetched, daemon_strategy(), &config.chain);
+        (job, result)
+    });
+}
+
+fn finish(job: Job, outcome: Outcome) -> Finished {
+    Finished {
+        height: job.fetched.height,
+        block_hash: job.block_hash,
+        outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Duration;
+
+    use methods::types::{BitcoinNetwork, BlockProofJournal, ProofError};
+    use tokio::sync::mpsc;
+    use tokio::task::JoinHandle;
+
+    use crate::block_source::{testing, MemorySource};
+    use crate::daemon_state::DaemonState;
+    use crate::{ChainConfig, OutputFormat, PrescanMode};
+
+    use super::*;
+
+    const NETWORK: BitcoinNetwork = BitcoinNetwork::Regtest;
+
+    /// A persist stage that has committed everything below `committed`
+    struct Stage {
+        finished: Sender<Item<Finished>>,
+        restarts: Receiver<Restart>,
+        progress: Arc<Progress>,
+        task: JoinHandle<anyhow::Result<()>>,
+    }
+
+    fn start(output_dir: &Path, source: MemorySource, committed: u64) -> Stage {
+        let config = DaemonConfig {
+            start_height: None,
+            output_dir: output_dir.display().to_string(),
+            overwrite: false,
+            format: OutputFormat::Json,
+            prescan: PrescanMode::Skip,
+            chain: ChainConfig {
+                network: NETWORK,
+                signet_challenge: None,
+            },
+            workers: 1,
+            prefetch: 1,
+            confirmations: 1,
+            fast_path: false,
+            poll_interval: Duration::from_millis(10),
+            retry_delay: Duration::from_millis(10),
+        };
+        let mut chain = ProvenChain::new();
+        for height in 0..committed {
+            chain.push(height, source.block_hash(height).unwrap());
+        }
+        let store = ProofStore::open(output_dir, NETWORK).unwrap();
+        let progress = Arc::new(Progress {
+            committed: AtomicU64::new(committed),
+            next_unconfirmed: AtomicU64::new(u64::MAX),
+        });
+        let (finished, finished_rx) = mpsc::channel(16);
+        let (restarts_tx, restarts) = mpsc::channel(1);
+        let task = tokio::spawn(persist_stage(
+            Arc::new(config),
+            Arc::new(source),
+            chain,
+            store,
+            finished_rx,
+            restarts_tx,
+            progress.clone(),
+        ));
+        Stage {
+            finished,
+            restarts,
+            progress,
+            task,
+        }
+    }
+
+    fn done(height: u64, block_hash: &str, outcome: Outcome) -> Item<Finished> {
+        Item::Block(Finished {
+            height,
+            block_hash: block_hash.to_string(),
+            outcome,
+        })
+    }
+
+    /// Writes the proof file a previous run left for a block
+    fn write_proof(output_dir: &Path, height: u64, block_hash: &str) {
+        let proof = testing::proof(height, block_hash, NETWORK);
+        fs::write(
+            output_dir.join(format!("block_{}.json", height)),
+            serde_json::to_string(&proof).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_commits_in_height_order() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let mut source = MemorySource::new();
+        let hashes = testing::extend(&mut source, 0, 7, 0);
+        write_proof(output_dir, 2, &hashes[2]);
+        let mut stage = start(output_dir, source, 1);
+
+        // Block 4 never finishes, so 5 and 6 stay above the gap
+        let sends = [
+            done(3, &hashes[3], Outcome::Skipped),
+            done(6, &hashes[6], Outcome::Skipped),
+            done(2, &hashes[2], Outcome::Kept),
+            done(5, &hashes[5], Outcome::Skipped),
+            done(1, &hashes[1], Outcome::Skipped),
+        ];
+        for item in sends {
+            stage.finished.send(item).await.unwrap();
+        }
+        drop(stage.finished);
+        stage.task.await.unwrap().unwrap();
+        assert!(stage.restarts.recv().await.is_none());
+
+        assert_eq!(stage.progress.committed.load(Ordering::SeqCst), 4);
+        let state = DaemonState::load(output_dir).unwrap().unwrap();
+        assert_eq!(state.network, NETWORK);
+        assert_eq!(
+            (state.last_height, state.last_hash.as_str()),
+            (3, hashes[3].as_str())
+        );
+        for (height, block_hash) in hashes.iter().enumerate().take(4) {
+            assert_eq!(
+                state.recent_blocks.hash_at(height as u64),
+                Some(block_hash.as_str())
+            );
+        }
+        assert_eq!(state.recent_blocks.hash_at(5), None);
+        assert_eq!(state.recent_blocks.hash_at(6), None);
+
+        // The kept proof file reached the store
+        let store = ProofStore::open(output_dir, NETWORK).unwrap();
+        assert_eq!(store.tip().unwrap(), Some((2, Some(hashes[2].clone()))));
+    }
+
+    fn proven(height: u64, block_hash: &str, journal: BlockProofJournal) -> Item<Finished> {
+        let bundle = testing::bundle(&journal, NETWORK);
+        done(height, block_hash, Outcome::Proven(Box::new(bundle)))
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_is_retried_before_commit() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let mut source = MemorySource::new();
+        let hashes = testing::extend(&mut source, 0, 2, 0);
+        let proof = testing::proof(1, &hashes[1], NETWORK);
+        // The bundle can't be written while a directory is in its place
+        let bundle_file = output_dir.join("block_1.bundle");
+        fs::create_dir(&bundle_file).unwrap();
+        let mut stage = start(output_dir, source, 1);
+
+        stage
+            .finished
+            .send(proven(1, &hashes[1], Ok(proof)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(stage.progress.committed.load(Ordering::SeqCst), 1);
+        assert!(DaemonState::load(output_dir).unwrap().is_none());
+        // Nor is the proof indexed while it isn't on disk
+        let store = ProofStore::open(output_dir, NETWORK).unwrap();
+        assert!(!store.contains(1).unwrap());
+
+        // Committed once the write goes through
+        fs::remove_dir(&bundle_file).unwrap();
+        drop(stage.finished);
+        stage.task.await.unwrap().unwrap();
+        assert!(stage.restarts.recv().await.is_none());
+        assert_eq!(stage.progress.committed.load(Ordering::SeqCst), 2);
+        assert!(bundle_file.is_file());
+        assert!(output_dir.join("block_1.json").is_file());
+        let state = DaemonState::load(output_dir).unwrap().unwrap();
+        assert_eq!(state.last_height, 1);
+        let summary = store.summary(1).unwrap().unwrap();
+        assert!(summary.proven);
+        assert!(summary.has_bundle);
+    }
+
+    #[tokio::test]
+    async fn test_committed_rejection_is_final() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let mut source = MemorySource::new();
+        let hashes = testing::extend(&mut source, 0, 2, 0);
+        let failure = BlockProofFailure {
+            block_height: 1,
+            block_hash: Some(hashes[1].clone()),
+            network_magic: NETWORK.magic(),
+            signet_challenge_hash: None,
+            strategy: daemon_strategy(),
+            error: ProofError::MerkleRootMismatch,
+        };
+        let stage = start(output_dir, source, 1);
+
+        stage
+            .finished
+            .send(proven(1, &hashes[1], Err(failure)))
+            .await
+            .unwrap();
+        drop(stage.finished);
+        stage.task.await.unwrap().unwrap();
+
+        // The bundle is the record of the rejection, and the daemon moves on
+        assert_eq!(stage.progress.committed.load(Ordering::SeqCst), 2);
+        assert!(output_dir.join("block_1.bundle").is_file());
+        assert!(!output_dir.join("block_1.json").exists());
+        let state = DaemonState::load(output_dir).unwrap().unwrap();
+        assert_eq!(state.last_height, 1);
+        // and is indexed as a rejection
+        let store = ProofStore::open(output_dir, NETWORK).unwrap();
+        let summary = store.summary(1).unwrap().unwrap();
+        assert!(!summary.proven);
+        assert!(summary.has_bundle);
+        assert_eq!(summary.block_hash.as_deref(), Some(hashes[1].as_str()));
+        assert!(summary.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_reorg_sets_aside_proofs_above_gap() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let mut source = MemorySource::new();
+        let old_hashes = testing::extend(&mut source, 0, 5, 0);
+        write_proof(output_dir, 3, &old_hashes[3]);
+        testing::fork(&mut source, 2, 3, 1);
+        let mut stage = start(output_dir, source, 1);
+
+        let sends = [
+            done(1, &old_hashes[1], Outcome::Skipped),
+            // Block 2 is still being proven when the reorg is found
+            done(3, &old_hashes[3], Outcome::Kept),
+            done(4, &old_hashes[4], Outcome::Skipped),
+            Item::Reorg,
+        ];
+        for item in sends {
+            stage.finished.send(item).await.unwrap();
+        }
+        let restart = stage.restarts.recv().await.unwrap();
+        drop(stage.finished);
+        stage.task.await.unwrap().unwrap();
+
+        // Blocks 0 and 1 are still on the best chain
+        assert_eq!(restart.next_height, 2);
+        assert_eq!(restart.chain.last(), Some((1, old_hashes[1].as_str())));
+        assert_eq!(stage.progress.committed.load(Ordering::SeqCst), 2);
+        let state = DaemonState::load(output_dir).unwrap().unwrap();
+        assert_eq!(state.last_height, 1);
+
+        // The proof of the stale block 3 is set aside, and was never committed
+        assert!(!output_dir.join("block_3.json").exists());
+        assert!(output_dir
+            .join(format!("orphaned/block_3_{}.json", old_hashes[3]))
+            .exists());
+        assert!(!ProofStore::open(output_dir, NETWORK)
+            .unwrap()
+            .contains(3)
+            .unwrap());
+    }
+}
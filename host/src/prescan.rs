@@ -0,0 +1,265 @@
+use std::str::FromStr;
+
+use bitcoin::{Block, Txid};
+use methods::matching::check_transaction_patterns;
+use methods::types::{
+    BitcoinNetwork, CoreLanePatterns, ProofError, ProofStrategy, TransactionPattern,
+    TransactionType,
+};
+
+/// A transaction the guest is expected to commit
+#[derive(Debug, Clone)]
+pub struct PreScanMatch {
+    pub index: u32,
+    pub txid: Txid,
+    pub tx_type: TransactionType,
+}
+
+/// What the guest will find in a block, computed natively in milliseconds
+///
+/// Uses the guest's own matching code (`methods::matching`), so a pre-scan predicts the
+/// journal without proving anything. It is only a prediction: the receipt stays the
+/// source of truth.
+#[derive(Debug, Clone)]
+pub struct PreScan {
+    pub total_transactions: usize,
+    pub matches: Vec<PreScanMatch>,
+    /// Rejection the guest would commit for a pointing strategy
+    pub rejection: Option<ProofError>,
+}
+
+impl PreScan {
+    /// Whether proving would commit nothing of interest
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty() || self.rejection.is_some()
+    }
+}
+
+/// Matches a block's transactions against a strategy the way the guest does
+pub fn prescan(block: &Block, strategy: &ProofStrategy, network: BitcoinNetwork) -> PreScan {
+    let patterns = CoreLanePatterns::for_network(network);
+    let mut scan = PreScan {
+        total_transactions: block.txdata.len(),
+        matches: Vec::new(),
+        rejection: None,
+    };
+
+    match strategy {
+        ProofStrategy::Searching(searching_proof) => {
+            for (index, tx) in block.txdata.iter().enumerate() {
+                let index = index as u32;
+                if let Some(tx_type) =
+                    check_transaction_patterns(tx, index, &patterns, &searching_proof.pattern)
+                {
+                    scan.matches.push(PreScanMatch {
+                        index,
+                        txid: tx.compute_txid(),
+                        tx_type,
+                    });
+                }
+            }
+        }
+        ProofStrategy::Pointing(pointing_proof) => {
+            let target = match Txid::from_str(&pointing_proof.txid) {
+                Ok(target) => target,
+                Err(e) => {
+                    scan.rejection = Some(ProofError::InvalidStrategy(format!(
+                        "Invalid txid {}: {}",
+                        pointing_proof.txid, e
+                    )));
+                    return scan;
+                }
+            };
+            let Some((index, tx)) = block
+                .txdata
+                .iter()
+                .enumerate()
+                .find(|(_, tx)| tx.compute_txid() == target)
+            else {
+                scan.rejection = Some(ProofError::TransactionNotFound {
+                    txid: pointing_proof.txid.clone(),
+                });
+                return scan;
+            };
+
+            let index = index as u32;
+            match check_transaction_patterns(tx, index, &patterns, &TransactionPattern::All) {
+                None => {
+                    scan.rejection = Some(ProofError::NotCoreLaneTransaction {
+                        txid: pointing_proof.txid.clone(),
+                    })
+                }
+                Some(actual) if actual != pointing_proof.expected_type => {
+                    scan.rejection = Some(ProofError::TypeMismatch {
+                        txid: pointing_proof.txid.clone(),
+                        expected: pointing_proof.expected_type.clone(),
+                        actual,
+                    })
+                }
+                Some(tx_type) => scan.matches.push(PreScanMatch {
+                    index,
+                    txid: target,
+                    tx_type,
+                }),
+            }
+        }
+    }
+
+    scan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::script::PushBytesBuf;
+    use bitcoin::{absolute, transaction, Amount, BlockHash, ScriptBuf, Transaction, TxOut};
+    use methods::types::{PointingProof, SearchingProof};
+
+    use crate::block_source::testing;
+
+    /// Transaction with a single output carrying `payload` after OP_RETURN
+    fn op_return(payload: &[u8]) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: Vec::new(),
+            output: vec![TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return(
+                    PushBytesBuf::try_from(payload.to_vec()).unwrap(),
+                ),
+            }],
+        }
+    }
+
+    /// Coinbase, a BRN1 burn, a DA transaction, a short BRN1 payload and a fill
+    fn block() -> Block {
+        let raw_block = testing::block(BlockHash::all_zeros(), 0);
+        let mut block: Block = bitcoin::consensus::deserialize(&raw_block).unwrap();
+        let mut burn = b"BRN1".to_vec();
+        burn.resize(28, 0xab);
+        block.txdata.extend([
+            op_return(&burn),
+            op_return(b"CORE da blob"),
+            op_return(b"BRN1 too short"),
+            op_return(b"FILL"),
+        ]);
+        block
+    }
+
+    fn searching(pattern: TransactionPattern) -> ProofStrategy {
+        ProofStrategy::Searching(SearchingProof { pattern })
+    }
+
+    fn pointing(txid: String, expected_type: TransactionType) -> ProofStrategy {
+        ProofStrategy::Pointing(PointingProof {
+            txid,
+            tx_position: 0,
+            expected_type,
+        })
+    }
+
+    /// Index and type of every match, checked against the guest's classification
+    fn matches(
+        block: &Block,
+        scan: &PreScan,
+        pattern: &TransactionPattern,
+    ) -> Vec<(u32, TransactionType)> {
+        let patterns = CoreLanePatterns::default();
+        scan.matches
+            .iter()
+            .map(|found| {
+                let tx = &block.txdata[found.index as usize];
+                assert_eq!(found.txid, tx.compute_txid());
+                assert_eq!(
+                    check_transaction_patterns(tx, found.index, &patterns, pattern),
+                    Some(found.tx_type.clone())
+                );
+                (found.index, found.tx_type.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_prescan_searching() {
+        let block = block();
+        let network = BitcoinNetwork::Mainnet;
+        for (pattern, expected) in [
+            (
+                TransactionPattern::All,
+                vec![
+                    (1, TransactionType::Burn),
+                    (2, TransactionType::DataAvailability),
+                    (4, TransactionType::Fill),
+                ],
+            ),
+            (TransactionPattern::Burns, vec![(1, TransactionType::Burn)]),
+            (
+                TransactionPattern::DataAvailability,
+                vec![(2, TransactionType::DataAvailability)],
+            ),
+        ] {
+            let scan = prescan(&block, &searching(pattern.clone()), network);
+            assert_eq!(scan.total_transactions, 5);
+            assert!(scan.rejection.is_none());
+            assert_eq!(matches(&block, &scan, &pattern), expected, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_prescan_pointing() {
+        let block = block();
+        let network = BitcoinNetwork::Mainnet;
+        let txid = |index: usize| block.txdata[index].compute_txid().to_string();
+
+        let scan = prescan(&block, &pointing(txid(1), TransactionType::Burn), network);
+        assert!(scan.rejection.is_none());
+        assert_eq!(
+            matches(&block, &scan, &TransactionPattern::All),
+            [(1, TransactionType::Burn)]
+        );
+
+        let scan = prescan(
+            &block,
+            &pointing(txid(1), TransactionType::DataAvailability),
+            network,
+        );
+        assert!(scan.is_empty());
+        assert_eq!(
+            scan.rejection,
+            Some(ProofError::TypeMismatch {
+                txid: txid(1),
+                expected: TransactionType::DataAvailability,
+                actual: TransactionType::Burn,
+            })
+        );
+
+        let scan = prescan(&block, &pointing(txid(3), TransactionType::Burn), network);
+        assert_eq!(
+            scan.rejection,
+            Some(ProofError::NotCoreLaneTransaction { txid: txid(3) })
+        );
+
+        let missing = "ab".repeat(32);
+        let scan = prescan(
+            &block,
+            &pointing(missing.clone(), TransactionType::Burn),
+            network,
+        );
+        assert_eq!(
+            scan.rejection,
+            Some(ProofError::TransactionNotFound { txid: missing })
+        );
+
+        let scan = prescan(
+            &block,
+            &pointing("xyz".to_string(), TransactionType::Burn),
+            network,
+        );
+        assert!(matches!(
+            scan.rejection,
+            Some(ProofError::InvalidStrategy(_))
+        ));
+    }
+}
@@ -0,0 +1,841 @@
+//! Indexed proof store: an SQLite database next to the proof files
+//!
+//! Blocks are keyed by height and hash, with every matching transaction indexed by
+//! txid and type. Each block is written in a single transaction, so readers (e.g.
+//! `serve`) never see a proof without its transactions. A new store imports the
+//! proof files already in its directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use methods::types::{
+    BitcoinBlockProof, BitcoinNetwork, BlockProofJournal, ProofBundle, TransactionType,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::reorg::proof_files;
+
+/// Database file in the output directory
+pub const STORE_FILE: &str = "proofs.sqlite";
+
+/// Version of the schema below, kept in `PRAGMA user_version`
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE blocks (
+    height INTEGER PRIMARY KEY,
+    -- NULL only for rejections of blocks whose header didn't parse
+    block_hash TEXT UNIQUE,
+    network TEXT NOT NULL,
+    proven INTEGER NOT NULL,
+    -- Why the guest rejected the block, if it did
+    error TEXT,
+    -- CBOR BitcoinBlockProof, NULL for rejections
+    proof BLOB,
+    -- CBOR ProofBundle, NULL for proofs imported without one
+    bundle BLOB,
+    stored_at INTEGER NOT NULL
+);
+CREATE TABLE transactions (
+    txid TEXT NOT NULL,
+    height INTEGER NOT NULL,
+    tx_type TEXT NOT NULL,
+    PRIMARY KEY (txid, height)
+);
+CREATE INDEX transactions_by_height ON transactions (height);
+CREATE INDEX transactions_by_type ON transactions (tx_type, height);
+-- Blocks that left the best chain, as they were when orphaned
+CREATE TABLE orphaned_blocks (
+    height INTEGER NOT NULL,
+    block_hash TEXT NOT NULL,
+    network TEXT NOT NULL,
+    proven INTEGER NOT NULL,
+    error TEXT,
+    proof BLOB,
+    bundle BLOB,
+    orphaned_at INTEGER NOT NULL,
+    PRIMARY KEY (height, block_hash)
+);
+";
+
+/// How a block is looked up
+pub enum BlockKey<'a> {
+    Height(u64),
+    Hash(&'a str),
+    /// The block holding a matching transaction
+    Txid(&'a str),
+}
+
+/// Index entry of a stored block
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockSummary {
+    pub height: u64,
+    pub block_hash: Option<String>,
+    /// Whether the journal is a proof rather than a provable rejection
+    pub proven: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub matching_transactions: Vec<StoredTransaction>,
+    /// Whether the proof bundle with the receipt is stored
+    pub has_bundle: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredTransaction {
+    pub txid: String,
+    pub tx_type: String,
+}
+
+/// Outcome of [`ProofStore::import_dir`]
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub already_stored: usize,
+    pub unreadable: usize,
+}
+
+/// A block ready to be written
+struct NewBlock {
+    height: u64,
+    block_hash: Option<String>,
+    network: BitcoinNetwork,
+    error: Option<String>,
+    proof: Option<BitcoinBlockProof>,
+    bundle: Option<Vec<u8>>,
+}
+
+pub struct ProofStore {
+    conn: Connection,
+}
+
+impl ProofStore {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STORE_FILE)
+    }
+
+    /// Opens the store in `output_dir`, creating it and importing the proof files
+    /// already there on first use
+    pub fn open(output_dir: &Path, network: BitcoinNetwork) -> anyhow::Result<Self> {
+        let (mut store, created) = Self::connect(output_dir)?;
+        if created {
+            let summary = store.import_dir(output_dir, network)?;
+            if summary.imported > 0 || summary.unreadable > 0 {
+                println!(
+                    "📦 Created {} and imported {} existing proofs ({} unreadable)",
+                    Self::path(output_dir).display(),
+                    summary.imported,
+                    summary.unreadable
+                );
+            }
+        }
+        Ok(store)
+    }
+
+    /// Opens the store in `output_dir`, creating it if needed but importing nothing.
+    /// Also returns whether it was created.
+    pub fn connect(output_dir: &Path) -> anyhow::Result<(Self, bool)> {
+        let path = Self::path(output_dir);
+        let conn = Connection::open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+        // The daemon writes while `serve` reads
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let mut store = Self { conn };
+        match version {
+            0 => {
+                let tx = store.conn.transaction()?;
+                tx.execute_batch(SCHEMA)?;
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                tx.commit()?;
+                Ok((store, true))
+            }
+            SCHEMA_VERSION => Ok((store, false)),
+            _ => Err(anyhow::anyhow!(
+                "{} has schema version {}, this host only knows {}",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            )),
+        }
+    }
+
+    /// Stores a proven (or provably rejected) block, replacing what was stored at its
+    /// height
+    pub fn put(&mut self, bundle: &ProofBundle) -> anyhow::Result<()> {
+        let (block_hash, error, proof) = match &bundle.journal {
+            Ok(proof) => (Some(proof.block_hash.clone()), None, Some(proof.clone())),
+            Err(failure) => (
+                failure.block_hash.clone(),
+                Some(failure.error.to_string()),
+                None,
+            ),
+        };
+        self.write(NewBlock {
+            height: bundle.block_height(),
+            block_hash,
+            network: bundle.network,
+            error,
+            proof,
+            bundle: Some(bundle.to_cbor().map_err(|e| anyhow::anyhow!(e))?),
+        })
+    }
+
+    fn write(&mut self, block: NewBlock) -> anyhow::Result<()> {
+        let proof = block
+            .proof
+            .as_ref()
+            .map(|proof| proof.to_cbor())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "DELETE FROM transactions WHERE height = ?1",
+            params![block.height],
+        )?;
+        tx.execute(
+            "DELETE FROM blocks WHERE height = ?1",
+            params![block.height],
+        )?;
+        tx.execute(
+            "INSERT INTO blocks
+                (height, block_hash, network, proven, error, proof, bundle, stored_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                block.height,
+                block.block_hash,
+                block.network.to_string(),
+                block.proof.is_some(),
+                block.error,
+                proof,
+                block.bundle,
+                now(),
+            ],
+        )?;
+        for matching in block
+            .proof
+            .iter()
+            .flat_map(|proof| &proof.matching_transactions)
+        {
+            tx.execute(
+                "INSERT OR IGNORE INTO transactions (txid, height, tx_type) VALUES (?1, ?2, ?3)",
+                params![matching.txid, block.height, type_name(&matching.tx_type)],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn contains(&self, height: u64) -> anyhow::Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT 1 FROM blocks WHERE height = ?1",
+                params![height],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// Imports the `block_<height>.*` files in `output_dir`, skipping heights already
+    /// stored
+    ///
+    /// Receipts aren't re-verified: the files are trusted as much as the daemon that
+    /// wrote them.
+    pub fn import_dir(
+        &mut self,
+        output_dir: &Path,
+        network: BitcoinNetwork,
+    ) -> anyhow::Result<ImportSummary> {
+        let mut heights: Vec<u64> = fs::read_dir(output_dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let (stem, _) = name.rsplit_once('.')?;
+                stem.strip_prefix("block_")?.parse().ok()
+            })
+            .collect();
+        heights.sort_unstable();
+        heights.dedup();
+
+        let mut summary = ImportSummary::default();
+        for height in heights {
+            if self.contains(height)? {
+                summary.already_stored += 1;
+                continue;
+            }
+            match self.import_height(output_dir, height, network) {
+                Ok(true) => summary.imported += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    println!("⚠️  Not importing block {}: {}", height, e);
+                    summary.unreadable += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Imports the proof files of one height, preferring the bundle since it holds the
+    /// receipt. `false` if there are none.
+    pub fn import_height(
+        &mut self,
+        output_dir: &Path,
+        height: u64,
+        network: BitcoinNetwork,
+    ) -> anyhow::Result<bool> {
+        let files = proof_files(output_dir, height);
+        let file = |extension: &str| {
+            files
+                .iter()
+                .find(|file| file.extension().and_then(|ext| ext.to_str()) == Some(extension))
+        };
+
+        if let Some(file) = file("bundle") {
+            let bundle = ProofBundle::read(file).map_err(|e| anyhow::anyhow!(e))?;
+            if bundle.network != network {
+                return Err(anyhow::anyhow!(
+                    "bundle is for {}, not {}",
+                    bundle.network,
+                    network
+                ));
+            }
+            if bundle.block_height() != height {
+                return Err(anyhow::anyhow!(
+                    "file is named for height {} but proves block {}",
+                    height,
+                    bundle.block_height()
+                ));
+            }
+            self.put(&bundle)?;
+            return Ok(true);
+        }
+        let journal: BlockProofJournal = if let Some(file) = file("json") {
+            Ok(serde_json::from_slice(&fs::read(file)?)
+                .map_err(|e| anyhow::anyhow!("{} ({}): reprove it", e, file.display()))?)
+        } else if let Some(file) = file("cbor") {
+            Ok(BitcoinBlockProof::from_cbor(&fs::read(file)?).map_err(|e| anyhow::anyhow!(e))?)
+        } else {
+            return Ok(false);
+        };
+        let proof = journal.map_err(|failure| anyhow::anyhow!(failure))?;
+        if proof.block_height != height {
+            return Err(anyhow::anyhow!(
+                "file is named for height {} but proves block {}",
+                height,
+                proof.block_height
+            ));
+        }
+        if proof.network_magic != network.magic() {
+            return Err(anyhow::anyhow!(
+                "block {} was not proven on {}",
+                proof.block_hash,
+                network
+            ));
+        }
+        self.write(NewBlock {
+            height,
+            block_hash: Some(proof.block_hash.clone()),
+            network,
+            error: None,
+            proof: Some(proof),
+            bundle: None,
+        })?;
+        Ok(true)
+    }
+
+    /// Moves a block that left the best chain to `orphaned_blocks`, if it is the one
+    /// stored at its height
+    ///
+    /// A rejection stored without a hash (its header didn't parse) is taken to be that
+    /// block, and is recorded under `block_hash`.
+    pub fn orphan(&mut self, height: u64, block_hash: &str) -> anyhow::Result<bool> {
+        let tx = self.conn.transaction()?;
+        let moved = tx.execute(
+            "INSERT OR REPLACE INTO orphaned_blocks
+                (height, block_hash, network, proven, error, proof, bundle, orphaned_at)
+             SELECT height, COALESCE(block_hash, ?2), network, proven, error, proof, bundle, ?3
+             FROM blocks WHERE height = ?1 AND (block_hash = ?2 OR block_hash IS NULL)",
+            params![height, block_hash, now()],
+        )?;
+        if moved > 0 {
+            tx.execute("DELETE FROM blocks WHERE height = ?1", params![height])?;
+            tx.execute(
+                "DELETE FROM transactions WHERE height = ?1",
+                params![height],
+            )?;
+        }
+        tx.commit()?;
+        Ok(moved > 0)
+    }
+
+    /// Highest stored block
+    pub fn tip(&self) -> anyhow::Result<Option<(u64, Option<String>)>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT height, block_hash FROM blocks ORDER BY height DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    /// Height of the block `key` refers to
+    pub fn find(&self, key: BlockKey) -> anyhow::Result<Option<u64>> {
+        let (query, value) = match key {
+            BlockKey::Height(height) => return Ok(self.contains(height)?.then_some(height)),
+            BlockKey::Hash(block_hash) => (
+                "SELECT height FROM blocks WHERE block_hash = ?1",
+                block_hash,
+            ),
+            // A txid can only repeat in blocks from before BIP 30; the latest one wins
+            BlockKey::Txid(txid) => (
+                "SELECT height FROM transactions WHERE txid = ?1 ORDER BY height DESC LIMIT 1",
+                txid,
+            ),
+        };
+        Ok(self
+            .conn
+            .query_row(query, params![value], |row| row.get(0))
+            .optional()?)
+    }
+
+    /// Blocks with a matching transaction `txid`, and its type there, lowest first
+    pub fn transaction_blocks(&self, txid: &str) -> anyhow::Result<Vec<(u64, String)>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT height, tx_type FROM transactions WHERE txid = ?1 ORDER BY height",
+        )?;
+        let rows = statement.query_map(params![txid], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Summaries of the stored blocks in `from..=to`, optionally only those with a
+    /// transaction of `tx_type`
+    pub fn range(
+        &self,
+        from: u64,
+        to: u64,
+        tx_type: Option<&TransactionType>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<BlockSummary>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT height, block_hash, proven, error, bundle IS NOT NULL FROM blocks
+             WHERE height BETWEEN ?1 AND ?2
+               AND (?3 IS NULL OR height IN
+                    (SELECT height FROM transactions WHERE tx_type = ?3))
+             ORDER BY height LIMIT ?4",
+        )?;
+        let rows = statement.query_map(
+            params![
+                clamp(from),
+                clamp(to),
+                tx_type.map(type_name),
+                clamp(limit as u64)
+            ],
+            |row| {
+                Ok(BlockSummary {
+                    height: row.get(0)?,
+                    block_hash: row.get(1)?,
+                    proven: row.get(2)?,
+                    error: row.get(3)?,
+                    matching_transactions: Vec::new(),
+                    has_bundle: row.get(4)?,
+                })
+            },
+        )?;
+        let mut blocks = rows.collect::<Result<Vec<_>, _>>()?;
+        for block in &mut blocks {
+            block.matching_transactions = self.transactions(block.height)?;
+        }
+        Ok(blocks)
+    }
+
+    /// Summary of the stored block at `height`
+    pub fn summary(&self, height: u64) -> anyhow::Result<Option<BlockSummary>> {
+        Ok(self.range(height, height, None, 1)?.pop())
+    }
+
+    fn transactions(&self, height: u64) -> anyhow::Result<Vec<StoredTransaction>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT txid, tx_type FROM transactions WHERE height = ?1 ORDER BY rowid",
+        )?;
+        let rows = statement.query_map(params![height], |row| {
+            Ok(StoredTransaction {
+                txid: row.get(0)?,
+                tx_type: row.get(1)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// The CBOR proof at `height` (`None` for rejections)
+    pub fn proof_cbor(&self, height: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        self.blob("proof", height)
+    }
+
+    /// The CBOR proof bundle at `height`, if it was stored
+    pub fn bundle_cbor(&self, height: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        self.blob("bundle", height)
+    }
+
+    fn blob(&self, column: &str, height: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT {} FROM blocks WHERE height = ?1", column),
+                params![height],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten())
+    }
+}
+
+/// Name of a transaction type in the store, e.g. `Burn`
+pub fn type_name(tx_type: &TransactionType) -> String {
+    format!("{:?}", tx_type)
+}
+
+/// SQLite integers are signed
+fn clamp(value: u64) -> i64 {
+    value.min(i64::MAX as u64) as i64
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_source::testing;
+    use methods::types::{
+        BlockProofFailure, MatchingTransaction, ProofError, ProofStrategy, SearchingProof,
+        TransactionPattern,
+    };
+
+    use super::*;
+
+    const NETWORK: BitcoinNetwork = BitcoinNetwork::Regtest;
+
+    fn hash(n: u64) -> String {
+        format!("{:064x}", n)
+    }
+
+    /// Proof of block `height` with hash `hash(block)` and the given matches
+    fn proof(height: u64, block: u64, matches: &[(u64, TransactionType)]) -> BitcoinBlockProof {
+        let mut proof = testing::proof(height, &hash(block), NETWORK);
+        proof.matching_transactions = matches
+            .iter()
+            .map(|(txid, tx_type)| MatchingTransaction {
+                txid: hash(*txid),
+                tx_type: tx_type.clone(),
+            })
+            .collect();
+        proof.matching_count = matches.len() as u32;
+        proof
+    }
+
+    fn rejection(height: u64) -> BlockProofFailure {
+        BlockProofFailure {
+            block_height: height,
+            block_hash: None,
+            network_magic: NETWORK.magic(),
+            signet_challenge_hash: None,
+            strategy: ProofStrategy::Searching(SearchingProof {
+                pattern: TransactionPattern::All,
+            }),
+            error: ProofError::MalformedBlock("truncated header".to_string()),
+        }
+    }
+
+    fn bundle(journal: BlockProofJournal, network: BitcoinNetwork) -> ProofBundle {
+        testing::bundle(&journal, network)
+    }
+
+    fn txids(summary: &BlockSummary) -> Vec<&str> {
+        summary
+            .matching_transactions
+            .iter()
+            .map(|tx| tx.txid.as_str())
+            .collect()
+    }
+
+    fn open(output_dir: &Path) -> ProofStore {
+        ProofStore::connect(output_dir).unwrap().0
+    }
+
+    #[test]
+    fn test_put_replaces_height() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut store = open(output_dir.path());
+        store
+            .put(&bundle(
+                Ok(proof(5, 50, &[(1, TransactionType::Burn)])),
+                NETWORK,
+            ))
+            .unwrap();
+        store
+            .put(&bundle(
+                Ok(proof(5, 51, &[(2, TransactionType::Fill)])),
+                NETWORK,
+            ))
+            .unwrap();
+
+        let summary = store.summary(5).unwrap().unwrap();
+        assert_eq!(summary.block_hash, Some(hash(51)));
+        assert!(summary.proven && summary.has_bundle);
+        assert_eq!(txids(&summary), [hash(2)]);
+        assert_eq!(store.find(BlockKey::Hash(&hash(50))).unwrap(), None);
+        assert_eq!(store.find(BlockKey::Txid(&hash(1))).unwrap(), None);
+        let stored = ProofBundle::from_cbor(&store.bundle_cbor(5).unwrap().unwrap()).unwrap();
+        assert_eq!(stored.proof().unwrap().block_hash, hash(51));
+
+        // A write that fails half-way (block 51 is already stored at 5) changes nothing
+        store
+            .put(&bundle(
+                Ok(proof(6, 60, &[(3, TransactionType::Burn)])),
+                NETWORK,
+            ))
+            .unwrap();
+        store
+            .put(&bundle(
+                Ok(proof(6, 51, &[(4, TransactionType::Burn)])),
+                NETWORK,
+            ))
+            .unwrap_err();
+        let summary = store.summary(6).unwrap().unwrap();
+        assert_eq!(summary.block_hash, Some(hash(60)));
+        assert_eq!(txids(&summary), [hash(3)]);
+        assert_eq!(store.find(BlockKey::Txid(&hash(4))).unwrap(), None);
+
+        // Rejections are stored with their bundle only
+        store.put(&bundle(Err(rejection(7)), NETWORK)).unwrap();
+        let summary = store.summary(7).unwrap().unwrap();
+        assert_eq!(summary.block_hash, None);
+        assert!(!summary.proven && summary.has_bundle);
+        assert_eq!(
+            summary.error.as_deref(),
+            Some("malformed block: truncated header")
+        );
+        assert_eq!(store.proof_cbor(7).unwrap(), None);
+        assert_eq!(store.tip().unwrap(), Some((7, None)));
+    }
+
+    #[test]
+    fn test_transaction_indexes() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut store = open(output_dir.path());
+        assert_eq!(store.tip().unwrap(), None);
+        let blocks = [
+            proof(
+                10,
+                100,
+                &[(1, TransactionType::Burn), (2, TransactionType::Fill)],
+            ),
+            proof(11, 110, &[]),
+            proof(12, 120, &[(3, TransactionType::DataAvailability)]),
+            // A duplicate txid, as before BIP 30
+            proof(13, 130, &[(1, TransactionType::Burn)]),
+        ];
+        for proof in blocks {
+            store.put(&bundle(Ok(proof), NETWORK)).unwrap();
+        }
+
+        assert_eq!(store.find(BlockKey::Height(11)).unwrap(), Some(11));
+        assert_eq!(store.find(BlockKey::Height(14)).unwrap(), None);
+        assert_eq!(store.find(BlockKey::Hash(&hash(120))).unwrap(), Some(12));
+        assert_eq!(store.find(BlockKey::Txid(&hash(2))).unwrap(), Some(10));
+        assert_eq!(store.find(BlockKey::Txid(&hash(1))).unwrap(), Some(13));
+        assert_eq!(
+            store.transaction_blocks(&hash(1)).unwrap(),
+            [(10, "Burn".to_string()), (13, "Burn".to_string())]
+        );
+        assert!(store.transaction_blocks(&hash(9)).unwrap().is_empty());
+
+        let heights = |blocks: Vec<BlockSummary>| -> Vec<u64> {
+            blocks.iter().map(|block| block.height).collect()
+        };
+        assert_eq!(
+            heights(store.range(0, 100, None, 10).unwrap()),
+            [10, 11, 12, 13]
+        );
+        assert_eq!(heights(store.range(11, 12, None, 10).unwrap()), [11, 12]);
+        assert_eq!(
+            heights(store.range(0, u64::MAX, None, 2).unwrap()),
+            [10, 11]
+        );
+        let burns = store
+            .range(0, 100, Some(&TransactionType::Burn), 10)
+            .unwrap();
+        assert_eq!(heights(burns.clone()), [10, 13]);
+        // Filtered blocks still list all their matches, in block order
+        assert_eq!(txids(&burns[0]), [hash(1), hash(2)]);
+        let da = store
+            .range(0, 100, Some(&TransactionType::DataAvailability), 10)
+            .unwrap();
+        assert_eq!(heights(da), [12]);
+        assert_eq!(store.tip().unwrap(), Some((13, Some(hash(130)))));
+    }
+
+    #[test]
+    fn test_orphan() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut store = open(output_dir.path());
+        store
+            .put(&bundle(
+                Ok(proof(5, 50, &[(1, TransactionType::Burn)])),
+                NETWORK,
+            ))
+            .unwrap();
+        store.put(&bundle(Err(rejection(6)), NETWORK)).unwrap();
+
+        // Another block at the height is left alone
+        assert!(!store.orphan(5, &hash(51)).unwrap());
+        assert!(store.contains(5).unwrap());
+
+        assert!(store.orphan(5, &hash(50)).unwrap());
+        assert!(!store.contains(5).unwrap());
+        assert_eq!(store.find(BlockKey::Txid(&hash(1))).unwrap(), None);
+        assert!(!store.orphan(5, &hash(50)).unwrap());
+
+        // The rejection has no hash of its own
+        assert!(store.orphan(6, &hash(60)).unwrap());
+        assert!(!store.contains(6).unwrap());
+
+        let orphaned: Vec<(u64, String, bool)> = store
+            .conn
+            .prepare("SELECT height, block_hash, proven FROM orphaned_blocks ORDER BY height")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(orphaned, [(5, hash(50), true), (6, hash(60), false)]);
+    }
+
+    #[test]
+    fn test_import_dir() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let dir = output_dir.path();
+        let write = |name: &str, data: Vec<u8>| fs::write(dir.join(name), data).unwrap();
+        let json = |proof: &BitcoinBlockProof| serde_json::to_vec_pretty(proof).unwrap();
+
+        write(
+            "block_1.json",
+            json(&proof(1, 10, &[(1, TransactionType::Burn)])),
+        );
+        write("block_2.cbor", proof(2, 20, &[]).to_cbor().unwrap());
+        let proven = bundle(Ok(proof(3, 30, &[(3, TransactionType::Fill)])), NETWORK);
+        // The bundle is preferred over the JSON next to it
+        write("block_3.json", json(&proof(3, 31, &[])));
+        write("block_3.bundle", proven.to_cbor().unwrap());
+        write(
+            "block_4.bundle",
+            bundle(Err(rejection(4)), NETWORK).to_cbor().unwrap(),
+        );
+        // Named for the wrong height
+        write("block_5.json", json(&proof(6, 60, &[])));
+        write(
+            "block_7.bundle",
+            bundle(Ok(proof(8, 80, &[])), NETWORK).to_cbor().unwrap(),
+        );
+        // Proven on another network
+        let mut mainnet = proof(9, 90, &[]);
+        mainnet.network_magic = BitcoinNetwork::Mainnet.magic();
+        write("block_9.cbor", mainnet.to_cbor().unwrap());
+        let mainnet = bundle(Ok(proof(10, 100, &[])), BitcoinNetwork::Mainnet);
+        write("block_10.bundle", mainnet.to_cbor().unwrap());
+        write("block_11.json", b"{\"block_hash\": ".to_vec());
+        write("notes.json", b"{}".to_vec());
+
+        let mut store = open(dir);
+        let summary = store.import_dir(dir, NETWORK).unwrap();
+        assert_eq!(
+            (summary.imported, summary.already_stored, summary.unreadable),
+            (4, 0, 5)
+        );
+        let stored = store.range(0, u64::MAX, None, 100).unwrap();
+        let stored: Vec<_> = stored
+            .iter()
+            .map(|block| (block.height, block.block_hash.clone(), block.has_bundle))
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                (1, Some(hash(10)), false),
+                (2, Some(hash(20)), false),
+                (3, Some(hash(30)), true),
+                (4, None, true),
+            ]
+        );
+        assert_eq!(store.find(BlockKey::Txid(&hash(1))).unwrap(), Some(1));
+        assert_eq!(store.find(BlockKey::Txid(&hash(3))).unwrap(), Some(3));
+
+        let error = store
+            .import_height(dir, 5, NETWORK)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "file is named for height 5 but proves block 6");
+        let error = store
+            .import_height(dir, 7, NETWORK)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "file is named for height 7 but proves block 8");
+        let error = store
+            .import_height(dir, 9, NETWORK)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!("block {} was not proven on regtest", hash(90))
+        );
+        let error = store
+            .import_height(dir, 10, NETWORK)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "bundle is for mainnet, not regtest");
+        assert!(!store.import_height(dir, 12, NETWORK).unwrap());
+
+        // Rerunning only retries what isn't stored
+        let summary = store.import_dir(dir, NETWORK).unwrap();
+        assert_eq!(
+            (summary.imported, summary.already_stored, summary.unreadable),
+            (0, 4, 5)
+        );
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let (_, created) = ProofStore::connect(output_dir.path()).unwrap();
+        assert!(created);
+        let (store, created) = ProofStore::connect(output_dir.path()).unwrap();
+        assert!(!created);
+
+        store
+            .conn
+            .pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        drop(store);
+        let error = ProofStore::connect(output_dir.path())
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            error,
+            format!(
+                "{} has schema version 2, this host only knows 1",
+                ProofStore::path(output_dir.path()).display()
+            )
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::block_source::BlockSource;
+
+/// How many processed blocks are remembered; deeper reorgs need a manual restart
+pub const MAX_REORG_DEPTH: usize = 144;
+
+/// Files written for one block, moved together when it is orphaned
+pub const PROOF_EXTENSIONS: [&str; 3] = ["json", "cbor", "bundle"];
+
+/// Append-only log of [`ReorgEvent`]s in the output directory, one JSON object per line
+pub const REORG_LOG: &str = "reorgs.jsonl";
+
+/// Hashes of the most recently processed blocks, by height
+///
+/// Every block the daemon handles (proven or skipped by the pre-scan) is recorded, so
+/// the next block's `prev_blockhash` and the source's view of these heights can be
+/// checked against what the proofs on disk commit to.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProvenChain {
+    blocks: BTreeMap<u64, String>,
+}
+
+impl ProvenChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a processed block, forgetting the oldest beyond [`MAX_REORG_DEPTH`]
+    pub fn push(&mut self, height: u64, block_hash: String) {
+        self.blocks.insert(height, block_hash);
+        while self.blocks.len() > MAX_REORG_DEPTH {
+            self.blocks.pop_first();
+        }
+    }
+
+    pub fn hash_at(&self, height: u64) -> Option<&str> {
+        self.blocks.get(&height).map(String::as_str)
+    }
+
+    /// Last processed block
+    pub fn last(&self) -> Option<(u64, &str)> {
+        self.blocks
+            .last_key_value()
+            .map(|(&height, block_hash)| (height, block_hash.as_str()))
+    }
+
+    /// Forgets blocks at `height` and above
+    pub fn truncate(&mut self, height: u64) {
+        self.blocks.retain(|&block_height, _| block_height < height);
+    }
+
+    /// Whether the source still has the last processed block on its best chain
+    pub fn tip_matches(&self, source: &dyn BlockSource, source_tip: u64) -> anyhow::Result<bool> {
+        match self.blocks.last_key_value() {
+            Some((&height, _)) => self.matches_at(source, source_tip, height),
+            None => Ok(true),
+        }
+    }
+
+    /// Whether the source's best chain has the processed block at `height`
+    ///
+    /// Heights that were never processed (or were forgotten) trivially match.
+    pub fn matches_at(
+        &self,
+        source: &dyn BlockSource,
+        source_tip: u64,
+        height: u64,
+    ) -> anyhow::Result<bool> {
+        let Some(block_hash) = self.blocks.get(&height) else {
+            return Ok(true);
+        };
+        if height > source_tip {
+            return Ok(false);
+        }
+        Ok(source.block_hash(height)? == *block_hash)
+    }
+}
+
+/// A proven block that left the best chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanedBlock {
+    pub height: u64,
+    pub block_hash: String,
+    /// Block now at this height on the source's best chain, if the chain is that long
+    pub replaced_by: Option<String>,
+    /// Where its proof files were moved
+    pub moved_files: Vec<String>,
+}
+
+/// Record of one reorg, appended to [`REORG_LOG`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorgEvent {
+    pub detected_at: u64,
+    /// Last block shared by the old and new branch
+    pub common_ancestor_height: u64,
+    pub common_ancestor_hash: String,
+    /// Orphaned blocks, lowest first
+    pub orphaned: Vec<OrphanedBlock>,
+}
+
+/// Finds where the source's best chain left the proven one and cleans up after it
+///
+/// Proofs above the common ancestor are moved to `<output_dir>/orphaned/` (renamed
+/// `block_<height>_<hash>.*` so competing branches don't collide), forgotten by
+/// `chain`, and the event is appended to [`REORG_LOG`]. The caller resumes proving at
+/// `common_ancestor_height + 1`.
+pub fn handle_reorg(
+    chain: &mut ProvenChain,
+    source: &dyn BlockSource,
+    source_tip: u64,
+    output_dir: &Path,
+) -> anyhow::Result<ReorgEvent> {
+    let mut orphaned = Vec::new();
+    let mut ancestor = None;
+    for (&height, block_hash) in chain.blocks.iter().rev() {
+        let current = if height <= source_tip {
+            Some(source.block_hash(height)?)
+        } else {
+            None
+        };
+        if current.as_deref() == Some(block_hash.as_str()) {
+            ancestor = Some((height, block_hash.clone()));
+            break;
+        }
+        orphaned.push(OrphanedBlock {
+            height,
+            block_hash: block_hash.clone(),
+            replaced_by: current,
+            moved_files: Vec::new(),
+        });
+    }
+    let Some((common_ancestor_height, common_ancestor_hash)) = ancestor else {
+        return Err(anyhow::anyhow!(
+            "Reorg deeper than the last {} processed blocks, restart below the fork",
+            chain.blocks.len()
+        ));
+    };
+
+    orphaned.reverse();
+    for block in &mut orphaned {
+        chain.blocks.remove(&block.height);
+        block.moved_files = orphan_proof_files(output_dir, block.height, &block.block_hash)?;
+    }
+
+    let event = ReorgEvent {
+        detected_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+        common_ancestor_height,
+        common_ancestor_hash,
+        orphaned,
+    };
+    // Nothing moved if the source only wavered while a block was being proven
+    if !event.orphaned.is_empty() {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output_dir.join(REORG_LOG))?;
+        writeln!(log, "{}", serde_json::to_string(&event)?)?;
+    }
+
+    Ok(event)
+}
+
+/// Proof files written for `height` in the output directory
+pub fn proof_files(output_dir: &Path, height: u64) -> Vec<PathBuf> {
+    PROOF_EXTENSIONS
+        .iter()
+        .map(|extension| output_dir.join(format!("block_{}.{}", height, extension)))
+        .filter(|file| file.exists())
+        .collect()
+}
+
+/// Moves the proof files for `height` to `<output_dir>/orphaned/`, returning their new
+/// paths
+pub fn orphan_proof_files(
+    output_dir: &Path,
+    height: u64,
+    block_hash: &str,
+) -> anyhow::Result<Vec<String>> {
+    let orphan_dir = output_dir.join("orphaned");
+    let mut moved_files = Vec::new();
+    for file in proof_files(output_dir, height) {
+        fs::create_dir_all(&orphan_dir)?;
+        let extension = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let moved = orphan_dir.join(format!("block_{}_{}.{}", height, block_hash, extension));
+        fs::rename(&file, &moved)?;
+        moved_files.push(moved.display().to_string());
+    }
+    Ok(moved_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_source::{testing, MemorySource};
+
+    use super::*;
+
+    /// A source with blocks `0..count` and a chain that processed all of them
+    fn processed(count: u64) -> (MemorySource, ProvenChain) {
+        let mut source = MemorySource::new();
+        let mut chain = ProvenChain::new();
+        for (height, block_hash) in testing::extend(&mut source, 0, count, 0)
+            .into_iter()
+            .enumerate()
+        {
+            chain.push(height as u64, block_hash);
+        }
+        (source, chain)
+    }
+
+    fn touch(output_dir: &Path, name: &str) {
+        fs::write(output_dir.join(name), name).unwrap();
+    }
+
+    #[test]
+    fn test_reorg_moves_orphaned_proofs() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let output_dir = output_dir.path();
+        let (mut source, mut chain) = processed(10);
+        let old_hashes: Vec<String> = (8..10)
+            .map(|height| chain.hash_at(height).unwrap().to_string())
+            .collect();
+        for name in [
+            "block_7.json",
+            "block_8.json",
+            "block_8.bundle",
+            "block_9.cbor",
+        ] {
+            touch(output_dir, name);
+        }
+
+        let new_hashes = testing::fork(&mut source, 8, 3, 1);
+        assert!(!chain.tip_matches(&source, 10).unwrap());
+        let event = handle_reorg(&mut chain, &source, 10, output_dir).unwrap();
+
+        // Proving resumes right above the common ancestor
+        assert_eq!(event.common_ancestor_height, 7);
+        assert_eq!(event.common_ancestor_hash, source.block_hash(7).unwrap());
+        assert_eq!(chain.hash_at(7), Some(event.common_ancestor_hash.as_str()));
+        assert_eq!(chain.hash_at(8), None);
+
+        let orphaned: Vec<_> = event
+            .orphaned
+            .iter()
+            .map(|block| {
+                (
+                    block.height,
+                    block.block_hash.as_str(),
+                    block.replaced_by.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            orphaned,
+            [
+                (8, old_hashes[0].as_str(), Some(new_hashes[0].as_str())),
+                (9, old_hashes[1].as_str(), Some(new_hashes[1].as_str())),
+            ]
+        );
+
+        let orphan_dir = output_dir.join("orphaned");
+        let expected = [
+            vec![
+                orphan_dir.join(format!("block_8_{}.json", old_hashes[0])),
+                orphan_dir.join(format!("block_8_{}.bundle", old_hashes[0])),
+            ],
+            vec![orphan_dir.join(format!("block_9_{}.cbor", old_hashes[1]))],
+        ];
+        for (block, files) in event.orphaned.iter().zip(&expected) {
+            let moved: Vec<_> = files
+                .iter()
+                .map(|file| file.display().to_string())
+                .collect();
+            assert_eq!(block.moved_files, moved);
+            assert!(files.iter().all(|file| file.exists()));
+        }
+        assert!(proof_files(output_dir, 8).is_empty());
+        assert!(proof_files(output_dir, 9).is_empty());
+        assert_eq!(
+            proof_files(output_dir, 7),
+            [output_dir.join("block_7.json")]
+        );
+
+        let log = fs::read_to_string(output_dir.join(REORG_LOG)).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let logged: ReorgEvent = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(logged.common_ancestor_height, 7);
+        assert_eq!(logged.orphaned.len(), 2);
+        assert_eq!(
+            logged.orphaned[1].moved_files,
+            event.orphaned[1].moved_files
+        );
+
+        // The next reorg is appended
+        chain.push(8, new_hashes[0].clone());
+        testing::fork(&mut source, 8, 1, 2);
+        handle_reorg(&mut chain, &source, 8, output_dir).unwrap();
+        let log = fs::read_to_string(output_dir.join(REORG_LOG)).unwrap();
+        assert_eq!(log.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_reorg_to_shorter_chain() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let (mut source, mut chain) = processed(10);
+        let replacement = testing::fork(&mut source, 8, 1, 1);
+
+        let event = handle_reorg(&mut chain, &source, 8, output_dir.path()).unwrap();
+        assert_eq!(event.common_ancestor_height, 7);
+        let replaced_by: Vec<_> = event
+            .orphaned
+            .iter()
+            .map(|block| (block.height, block.replaced_by.clone()))
+            .collect();
+        assert_eq!(replaced_by, [(8, Some(replacement[0].clone())), (9, None)]);
+        assert!(event
+            .orphaned
+            .iter()
+            .all(|block| block.moved_files.is_empty()));
+    }
+
+    #[test]
+    fn test_no_reorg_writes_no_log() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let (source, mut chain) = processed(5);
+        touch(output_dir.path(), "block_4.json");
+
+        assert!(chain.tip_matches(&source, 4).unwrap());
+        let event = handle_reorg(&mut chain, &source, 4, output_dir.path()).unwrap();
+        assert_eq!(event.common_ancestor_height, 4);
+        assert!(event.orphaned.is_empty());
+        assert!(!output_dir.path().join(REORG_LOG).exists());
+        assert_eq!(proof_files(output_dir.path(), 4).len(), 1);
+    }
+
+    #[test]
+    fn test_reorg_deeper_than_max_depth() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let (mut source, mut chain) = processed(200);
+        assert_eq!(chain.hash_at(200 - MAX_REORG_DEPTH as u64 - 1), None);
+        assert!(chain.hash_at(200 - MAX_REORG_DEPTH as u64).is_some());
+
+        testing::fork(&mut source, 50, 150, 1);
+        let error = handle_reorg(&mut chain, &source, 199, output_dir.path())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Reorg deeper than the last {} processed blocks, restart below the fork",
+                MAX_REORG_DEPTH
+            )
+        );
+        assert!(!output_dir.path().join(REORG_LOG).exists());
+    }
+}
@@ -0,0 +1,387 @@
+//! `serve`: HTTP API over the proof store of a daemon output directory
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use methods::types::{BitcoinBlockProof, BitcoinNetwork, TransactionType};
+use serde::{Deserialize, Serialize};
+
+use crate::daemon_state::DaemonState;
+use crate::proof_store::{BlockKey, BlockSummary, ProofStore};
+
+/// Most blocks listed by one range request
+const MAX_RANGE: usize = 1000;
+
+/// Settings for [`run_server`]
+#[derive(Debug, Clone)]
+pub struct ServeConfig {
+    pub output_dir: String,
+    pub network: BitcoinNetwork,
+    pub listen: String,
+}
+
+struct ServerState {
+    store: Mutex<ProofStore>,
+}
+
+/// Serves proofs from the store in `config.output_dir` until the process is stopped
+///
+/// Every request queries the store, so blocks the daemon commits show up immediately.
+pub fn run_server(config: ServeConfig) -> anyhow::Result<()> {
+    let output_dir = PathBuf::from(&config.output_dir);
+    if !output_dir.is_dir() {
+        return Err(anyhow::anyhow!(
+            "Proof directory {} does not exist",
+            output_dir.display()
+        ));
+    }
+    if let Some(saved) = DaemonState::load(&output_dir)? {
+        if saved.network != config.network {
+            return Err(anyhow::anyhow!(
+                "{} holds {} proofs, not {}",
+                output_dir.display(),
+                saved.network,
+                config.network
+            ));
+        }
+    }
+
+    println!("🌐 Starting proof server");
+    println!("   Network: {}", config.network);
+    println!("   Output directory: {}", output_dir.display());
+    let store = ProofStore::open(&output_dir, config.network)?;
+    let state = Arc::new(ServerState {
+        store: Mutex::new(store),
+    });
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let listener = tokio::net::TcpListener::bind(&config.listen).await?;
+        println!("   Listening on http://{}", listener.local_addr()?);
+
+        let app = Router::new()
+            .route("/tip", get(tip))
+            .route("/blocks", get(blocks_in_range))
+            .route("/blocks/{height}", get(block_by_height))
+            .route("/blocks/hash/{block_hash}", get(block_by_hash))
+            .route("/tx/{txid}", get(block_by_txid))
+            .with_state(state);
+        axum::serve(listener, app).await?;
+        Ok(())
+    })
+}
+
+/// Runs a query on tokio's blocking threads, since SQLite calls block
+async fn with_store<T: Send + 'static>(
+    state: &Arc<ServerState>,
+    query: impl FnOnce(&ProofStore) -> anyhow::Result<T> + Send + 'static,
+) -> Result<T, ApiError> {
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || query(&state.store.lock().unwrap()))
+        .await
+        .map_err(internal)?
+        .map_err(internal)
+}
+
+/// Error response, sent as `{"error": "..."}`
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn not_found(what: String) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("No proof for {}", what))
+}
+
+fn internal(e: impl std::fmt::Display) -> ApiError {
+    ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+/// A block hash or txid from the URL, lowercased the way proofs store them
+fn parse_hash(hash: &str, what: &str) -> Result<String, ApiError> {
+    if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid {} {}: expected 64 hex digits", what, hash),
+        ));
+    }
+    Ok(hash.to_ascii_lowercase())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// The proof as JSON
+    Json,
+    /// The proof as CBOR
+    Cbor,
+    /// The CBOR proof bundle with the receipt, also the proof of a rejection
+    Bundle,
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<Format>,
+}
+
+impl FormatQuery {
+    /// `?format=` if given, otherwise CBOR for clients that accept it and JSON for the rest
+    fn resolve(&self, headers: &HeaderMap) -> Format {
+        self.format.unwrap_or_else(|| {
+            let accepts_cbor = headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .is_some_and(|accept| accept.contains("application/cbor"));
+            if accepts_cbor {
+                Format::Cbor
+            } else {
+                Format::Json
+            }
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Tip {
+    height: u64,
+    block_hash: Option<String>,
+}
+
+/// Highest block in the store
+///
+/// Not the daemon's last committed block, which may have been skipped by the pre-scan
+/// and have nothing to serve.
+async fn tip(State(state): State<Arc<ServerState>>) -> Result<Json<Tip>, ApiError> {
+    let (height, block_hash) = with_store(&state, |store| store.tip())
+        .await?
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "No proofs yet".to_string()))?;
+    Ok(Json(Tip { height, block_hash }))
+}
+
+#[derive(Deserialize)]
+struct RangeQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    /// Only blocks with a matching transaction of this type
+    #[serde(rename = "type")]
+    tx_type: Option<TransactionType>,
+}
+
+/// Summaries of the blocks in `from..=to`, at most [`MAX_RANGE`] of them
+async fn blocks_in_range(
+    State(state): State<Arc<ServerState>>,
+    Query(range): Query<RangeQuery>,
+) -> Result<Json<Vec<BlockSummary>>, ApiError> {
+    let from = range.from.unwrap_or(0);
+    let to = range.to.unwrap_or(u64::MAX);
+    if from > to {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("Empty range {}..={}", from, to),
+        ));
+    }
+    let blocks = with_store(&state, move |store| {
+        store.range(from, to, range.tx_type.as_ref(), MAX_RANGE)
+    })
+    .await?;
+    Ok(Json(blocks))
+}
+
+async fn block_by_height(
+    State(state): State<Arc<ServerState>>,
+    UrlPath(height): UrlPath<u64>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = query.resolve(&headers);
+    serve_block(state, format!("block {}", height), format, move |store| {
+        store.find(BlockKey::Height(height))
+    })
+    .await
+}
+
+async fn block_by_hash(
+    State(state): State<Arc<ServerState>>,
+    UrlPath(block_hash): UrlPath<String>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let block_hash = parse_hash(&block_hash, "block hash")?;
+    let format = query.resolve(&headers);
+    let what = format!("block {}", block_hash);
+    serve_block(state, what, format, move |store| {
+        store.find(BlockKey::Hash(&block_hash))
+    })
+    .await
+}
+
+/// Proof of the block holding a matching transaction
+async fn block_by_txid(
+    State(state): State<Arc<ServerState>>,
+    UrlPath(txid): UrlPath<String>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let txid = parse_hash(&txid, "txid")?;
+    let format = query.resolve(&headers);
+    let what = format!("transaction {}", txid);
+    serve_block(state, what, format, move |store| {
+        store.find(BlockKey::Txid(&txid))
+    })
+    .await
+}
+
+/// Sends the block `find` picks in `format`
+async fn serve_block(
+    state: Arc<ServerState>,
+    what: String,
+    format: Format,
+    find: impl FnOnce(&ProofStore) -> anyhow::Result<Option<u64>> + Send + 'static,
+) -> Result<Response, ApiError> {
+    let (summary, body) = with_store(&state, move |store| {
+        let Some(height) = find(store)? else {
+            return Ok((None, None));
+        };
+        let body = match format {
+            Format::Json | Format::Cbor => store.proof_cbor(height)?,
+            Format::Bundle => store.bundle_cbor(height)?,
+        };
+        Ok((store.summary(height)?, body))
+    })
+    .await?;
+    let summary = summary.ok_or_else(|| not_found(what))?;
+
+    let body = match (body, format) {
+        (Some(cbor), Format::Json) => {
+            let proof = BitcoinBlockProof::from_cbor(&cbor).map_err(internal)?;
+            serde_json::to_vec_pretty(&proof).map_err(internal)?
+        }
+        (Some(cbor), _) => cbor,
+        (None, Format::Bundle) => {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("No proof bundle for block {}", summary.height),
+            ))
+        }
+        // The bundle still proves the rejection
+        (None, _) => {
+            return Err(ApiError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Block {} was rejected: {}; request format=bundle for the proof of rejection",
+                    summary.height,
+                    summary.error.as_deref().unwrap_or("unknown error")
+                ),
+            ))
+        }
+    };
+    let content_type = match format {
+        Format::Json => "application/json",
+        Format::Cbor | Format::Bundle => "application/cbor",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::block_source::testing;
+    use crate::reorg::ProvenChain;
+
+    use super::*;
+
+    const NETWORK: BitcoinNetwork = BitcoinNetwork::Regtest;
+
+    /// Server state over the store in `output_dir`
+    fn serving(output_dir: &Path) -> Arc<ServerState> {
+        Arc::new(ServerState {
+            store: Mutex::new(ProofStore::open(output_dir, NETWORK).unwrap()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_tip_is_highest_stored_block() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let ApiError(status, _) = tip(State(serving(output_dir.path()))).await.err().unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // The daemon went on to skip blocks 6 and 7, which have no proof
+        let output_dir = tempfile::tempdir().unwrap();
+        let block_hash = format!("{:064x}", 5);
+        let proof = testing::proof(5, &block_hash, NETWORK);
+        fs::write(
+            output_dir.path().join("block_5.json"),
+            serde_json::to_vec(&proof).unwrap(),
+        )
+        .unwrap();
+        let mut chain = ProvenChain::new();
+        for height in 5..8 {
+            chain.push(height, format!("{:064x}", height));
+        }
+        DaemonState::capture(NETWORK, &chain, u64::MAX)
+            .unwrap()
+            .save(output_dir.path())
+            .unwrap();
+        let Json(tip) = tip(State(serving(output_dir.path()))).await.ok().unwrap();
+        assert_eq!((tip.height, tip.block_hash), (5, Some(block_hash)));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_by_txid_and_hash() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let block_hash = "ab".repeat(32);
+        let proof = testing::proof(5, &block_hash, NETWORK);
+        let txid = proof.matching_transactions[0].txid.clone();
+        fs::write(
+            output_dir.path().join("block_5.json"),
+            serde_json::to_vec(&proof).unwrap(),
+        )
+        .unwrap();
+        let state = serving(output_dir.path());
+        let by_txid = |txid: String| {
+            block_by_txid(
+                State(state.clone()),
+                UrlPath(txid),
+                Query(FormatQuery { format: None }),
+                HeaderMap::new(),
+            )
+        };
+        let by_hash = |block_hash: String| {
+            block_by_hash(
+                State(state.clone()),
+                UrlPath(block_hash),
+                Query(FormatQuery { format: None }),
+                HeaderMap::new(),
+            )
+        };
+
+        // Either case finds the block
+        for txid in [txid.clone(), txid.to_ascii_uppercase()] {
+            assert_eq!(by_txid(txid).await.ok().unwrap().status(), StatusCode::OK);
+        }
+        let response = by_hash(block_hash.to_ascii_uppercase()).await.ok().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let ApiError(status, _) = by_txid("cd".repeat(32)).await.err().unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        for invalid in [
+            txid[1..].to_string(),
+            "zz".repeat(32),
+            format!("{}00", txid),
+        ] {
+            let ApiError(status, message) = by_txid(invalid).await.err().unwrap();
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", message);
+        }
+        let ApiError(status, _) = by_hash("not-a-hash".to_string()).await.err().unwrap();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,281 +1,311 @@
+use std::str::FromStr;
+
+use bitcoin::block::Header;
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::consensus::Decodable;
 use bitcoin::hashes::Hash;
-use bitcoin::{Block, Transaction};
+use bitcoin::params::Params;
+use bitcoin::{BlockHash, Network, Transaction, Txid};
 use risc0_zkvm::guest::env;
 
-use crate::merkle_simple::{MerkleProof, MerkleTree};
+use crate::hash;
+use crate::matching::check_transaction_patterns;
+use crate::merkle_simple::MerkleAccumulator;
+use crate::signet::SignetVerifier;
 use crate::types::{
-    BitcoinBlockInput, BitcoinBlockProof, CoreLanePatterns, MatchingTransaction, PointingProof,
-    ProofStrategy, SearchingProof, TransactionPattern, TransactionType,
+    BitcoinBlockInput, BitcoinBlockProof, BitcoinNetwork, BlockProofFailure, CoreLanePatterns,
+    MatchingTransaction, PointingProof, ProofError, ProofStrategy, SearchingProof,
+    TransactionPattern, TransactionType,
 };
 
 /// Processes a Bitcoin block and extracts Core Lane relevant transactions
-pub fn process_bitcoin_block(input: &BitcoinBlockInput) -> Result<BitcoinBlockProof, String> {
-    env::log("Starting Bitcoin block processing...");
-    env::log(&format!(
-        "Input block size: {} bytes",
-        input.raw_block.len()
-    ));
+///
+/// The block is consensus-decoded from `reader` one transaction at a time. Each
+/// transaction is matched, folded into the Merkle accumulator and dropped, so guest
+/// memory stays flat regardless of block size.
+///
+/// Rejections are returned as a [`BlockProofFailure`] so they can be committed to the
+/// journal instead of panicking the guest.
+#[allow(clippy::result_large_err)] // Same shape as the committed `BlockProofJournal`
+pub fn process_bitcoin_block<R: bitcoin::io::BufRead + ?Sized>(
+    input: &BitcoinBlockInput,
+    reader: &mut R,
+) -> Result<BitcoinBlockProof, BlockProofFailure> {
+    let mut block_hash = None;
+    process_block_stream(input, reader, &mut block_hash).map_err(|error| BlockProofFailure {
+        block_height: input.block_height,
+        block_hash,
+        network_magic: input.network.magic(),
+        signet_challenge_hash: input.signet_challenge.as_deref().map(hash::sha256),
+        strategy: input.strategy.clone(),
+        error,
+    })
+}
 
-    // Parse the raw block
-    env::log("Parsing raw block...");
-    let block: Block = bitcoin::consensus::deserialize(&input.raw_block)
-        .map_err(|e| format!("Failed to parse Bitcoin block: {}", e))?;
-    env::log(&format!(
-        "Block parsed successfully. Transaction count: {}",
-        block.txdata.len()
-    ));
+fn process_block_stream<R: bitcoin::io::BufRead + ?Sized>(
+    input: &BitcoinBlockInput,
+    reader: &mut R,
+    block_hash_out: &mut Option<String>,
+) -> Result<BitcoinBlockProof, ProofError> {
+    env::log("Starting Bitcoin block processing...");
 
-    // Compute block hash (this commits to the entire block including merkle root)
-    env::log("Computing block hash...");
-    let block_hash = block.block_hash().to_string();
+    // Parse the header (the block hash commits to the merkle root checked below)
+    let header = Header::consensus_decode(reader)
+        .map_err(|e| ProofError::MalformedBlock(format!("Failed to parse block header: {}", e)))?;
+    let block_hash = BlockHash::from_byte_array(hash::block_hash(&header));
     env::log(&format!("Block hash computed: {}", block_hash));
+    *block_hash_out = Some(block_hash.to_string());
+
+    // The header must be valid work on the requested network, so e.g. a regtest
+    // block can't be proven as mainnet. This only bounds the target by the network's
+    // powLimit: mainnet and testnet4 share one, so a testnet4 block mined above the
+    // minimum difficulty passes as mainnet. Only checking the block hash against the
+    // chain (`verify --check-chain`) tells those apart.
+    let params = Params::new(consensus_network(input.network));
+    let target = header.target();
+    if target > params.max_attainable_target || !target.is_met_by(block_hash) {
+        return Err(ProofError::InvalidProofOfWork);
+    }
+    let block_hash = block_hash.to_string();
+
+    // Signet blocks are signed rather than mined; genesis is the only unsigned block
+    let mut signet = match (input.network, &input.signet_challenge) {
+        (BitcoinNetwork::Signet, Some(challenge)) => (header.prev_blockhash
+            != BlockHash::all_zeros())
+        .then(|| SignetVerifier::new(challenge)),
+        (BitcoinNetwork::Signet, None) => {
+            return Err(ProofError::InvalidStrategy(
+                "A signet challenge is required on signet".to_string(),
+            ))
+        }
+        (_, Some(_)) => {
+            return Err(ProofError::InvalidStrategy(
+                "A signet challenge is only valid on signet".to_string(),
+            ))
+        }
+        (_, None) => None,
+    };
+
+    let tx_count = VarInt::consensus_decode(reader)
+        .map_err(|e| {
+            ProofError::MalformedBlock(format!("Failed to parse transaction count: {}", e))
+        })?
+        .0;
+    env::log(&format!("Streaming {} transactions...", tx_count));
 
-    // Process based on strategy
-    match &input.strategy {
+    let mut matcher = match &input.strategy {
         ProofStrategy::Searching(searching_proof) => {
-            process_searching_strategy(&block, searching_proof, &block_hash, input.block_height)
+            env::log("Using searching strategy...");
+            Matcher::Searching(searching_proof)
         }
         ProofStrategy::Pointing(pointing_proof) => {
-            process_pointing_strategy(&block, pointing_proof, &block_hash, input.block_height)
+            env::log("Using pointing strategy...");
+            env::log(&format!(
+                "Looking for transaction {} at position {}",
+                pointing_proof.txid, pointing_proof.tx_position
+            ));
+            let target = Txid::from_str(&pointing_proof.txid).map_err(|e| {
+                ProofError::InvalidStrategy(format!("Invalid txid {}: {}", pointing_proof.txid, e))
+            })?;
+            Matcher::Pointing {
+                pointing_proof,
+                target: target.to_byte_array(),
+                outcome: None,
+            }
         }
-    }
-}
-
-/// Process using searching strategy - find transactions by pattern
-fn process_searching_strategy(
-    block: &Block,
-    searching_proof: &SearchingProof,
-    block_hash: &str,
-    block_height: u64,
-) -> Result<BitcoinBlockProof, String> {
-    env::log("Using searching strategy...");
+    };
 
-    let patterns = CoreLanePatterns::default();
+    let patterns = CoreLanePatterns::for_network(input.network);
+    let mut accumulator = MerkleAccumulator::new();
     let mut matching_transactions = Vec::new();
+    let mut matching_txids = Vec::new();
+
+    for index in 0..tx_count {
+        let tx = Transaction::consensus_decode(reader).map_err(|e| {
+            ProofError::MalformedBlock(format!("Failed to parse transaction {}: {}", index, e))
+        })?;
+        let txid = hash::txid(&tx);
+        if let Some(signet) = &mut signet {
+            signet.push(&tx, txid);
+        }
 
-    for (index, tx) in block.txdata.iter().enumerate() {
-        if let Some((tx_type, txid)) =
-            check_transaction_patterns(tx, index as u32, &patterns, &searching_proof.pattern)?
-        {
+        let matched = matcher.check(&tx, &txid, index as u32, &patterns);
+        if let Some(tx_type) = &matched {
+            matching_txids.push(txid);
+            let txid = Txid::from_byte_array(txid).to_string();
             env::log(&format!(
                 "Found matching transaction: {} (type: {:?})",
                 txid, tx_type
             ));
+            matching_transactions.push(MatchingTransaction {
+                txid,
+                tx_type: tx_type.clone(),
+            });
+        }
 
-            let matching_tx = MatchingTransaction {
-                txid: txid.clone(),
-                tx_type,
-            };
+        accumulator.push(txid, matched.is_some());
+    }
 
-            matching_transactions.push(matching_tx);
-        }
+    // The computed root must match the header, otherwise the streamed
+    // transactions are not the ones the block hash commits to. A matching root
+    // alone isn't enough (CVE-2012-2459): `finalize` also rejects lists with
+    // repeated transactions that hash to the same root. This is checked before
+    // any pointing rejection so that rejections are bound to the block.
+    let total_transactions = accumulator.leaf_count();
+    let (merkle_root, mut merkle_multiproof) =
+        accumulator.finalize().map_err(ProofError::MalformedBlock)?;
+    if merkle_root != header.merkle_root.to_byte_array() {
+        return Err(ProofError::MerkleRootMismatch);
+    }
+    if let Some(signet) = signet {
+        signet
+            .verify(&header)
+            .map_err(ProofError::InvalidSignetSolution)?;
+        env::log("Signet block solution verified");
     }
 
+    let mut merkle_proofs = Vec::new();
+    let strategy = match matcher {
+        Matcher::Searching(searching_proof) => {
+            // One multiproof covers every match instead of a path each
+            if let Some(multiproof) = &merkle_multiproof {
+                if !multiproof.verify(&matching_txids, &merkle_root) {
+                    return Err(ProofError::MerkleProofFailed);
+                }
+                env::log(&format!(
+                    "Merkle multiproof for {} matches generated and verified ({} hashes)",
+                    multiproof.indices.len(),
+                    multiproof.hashes.len()
+                ));
+            }
+            ProofStrategy::Searching(searching_proof.clone())
+        }
+        Matcher::Pointing {
+            pointing_proof,
+            target,
+            outcome,
+        } => {
+            match outcome {
+                None => {
+                    return Err(ProofError::TransactionNotFound {
+                        txid: pointing_proof.txid.clone(),
+                    })
+                }
+                Some(Err(rejection)) => return Err(rejection),
+                Some(Ok(())) => {}
+            }
+            // The pointed transaction is the only tracked leaf, so the multiproof holds
+            // exactly its path
+            let merkle_proof = merkle_multiproof
+                .take()
+                .map(|multiproof| multiproof.proof_for(&matching_txids, &target))
+                .transpose()
+                .map_err(|_| ProofError::MerkleProofFailed)?
+                .flatten()
+                .ok_or(ProofError::MerkleProofFailed)?;
+            if !merkle_proof
+                .verify_proof(&merkle_root)
+                .map_err(|_| ProofError::MerkleProofFailed)?
+            {
+                return Err(ProofError::MerkleProofFailed);
+            }
+            env::log("Merkle proof generated and verified successfully");
+            merkle_proofs.push(merkle_proof);
+            ProofStrategy::Pointing(pointing_proof.clone())
+        }
+    };
+
     let matching_count = matching_transactions.len() as u32;
     env::log(&format!(
         "Found {} matching transactions out of {}",
-        matching_count,
-        block.txdata.len()
+        matching_count, total_transactions
     ));
 
     Ok(BitcoinBlockProof {
-        block_hash: block_hash.to_string(),
-        block_height,
-        strategy: ProofStrategy::Searching(searching_proof.clone()),
+        block_hash,
+        block_height: input.block_height,
+        network_magic: input.network.magic(),
+        signet_challenge_hash: input.signet_challenge.as_deref().map(hash::sha256),
+        strategy,
         matching_transactions,
-        merkle_proofs: Vec::new(), // No Merkle proofs for searching
-        total_transactions: block.txdata.len() as u32,
+        merkle_proofs,
+        merkle_multiproof,
+        total_transactions,
         matching_count,
     })
 }
 
-/// Process using pointing strategy - prove specific transaction exists
-fn process_pointing_strategy(
-    block: &Block,
-    pointing_proof: &PointingProof,
-    block_hash: &str,
-    block_height: u64,
-) -> Result<BitcoinBlockProof, String> {
-    env::log("Using pointing strategy...");
-    env::log(&format!(
-        "Looking for transaction {} at position {}",
-        pointing_proof.txid, pointing_proof.tx_position
-    ));
-
-    // Find the transaction by ID (ignore the provided position for now)
-    let mut found_position = None;
-    for (index, tx) in block.txdata.iter().enumerate() {
-        let actual_txid = tx.compute_txid().to_string();
-        if actual_txid == pointing_proof.txid {
-            found_position = Some(index);
-            break;
-        }
-    }
-
-    let tx_position = found_position
-        .ok_or_else(|| format!("Transaction {} not found in block", pointing_proof.txid))?
-        as u32;
-
-    let tx = &block.txdata[tx_position as usize];
-    env::log(&format!("Found transaction at position {}", tx_position));
-
-    // Verify the transaction matches the expected type
-    let patterns = CoreLanePatterns::default();
-    let (actual_type, _) = check_transaction_patterns(
-        tx,
-        pointing_proof.tx_position,
-        &patterns,
-        &TransactionPattern::All,
-    )?
-    .ok_or_else(|| {
-        format!(
-            "Transaction {} does not match any Core Lane pattern",
-            pointing_proof.txid
-        )
-    })?;
-
-    if actual_type != pointing_proof.expected_type {
-        return Err(format!(
-            "Transaction type mismatch: expected {:?}, got {:?}",
-            pointing_proof.expected_type, actual_type
-        ));
+/// Consensus parameters to check the header against
+fn consensus_network(network: BitcoinNetwork) -> Network {
+    match network {
+        BitcoinNetwork::Mainnet => Network::Bitcoin,
+        BitcoinNetwork::Testnet4 => Network::Testnet4,
+        BitcoinNetwork::Signet => Network::Signet,
+        BitcoinNetwork::Regtest => Network::Regtest,
     }
-
-    // Build Merkle tree and generate proof
-    env::log("Building Merkle tree...");
-    let txids: Vec<[u8; 32]> = block
-        .txdata
-        .iter()
-        .map(|tx| tx.compute_txid().to_byte_array())
-        .collect();
-
-    let merkle_tree = MerkleTree::build_merkle_tree(&txids)?;
-    let merkle_proof = merkle_tree.generate_proof(tx_position)?;
-
-    // Verify the proof
-    if !merkle_proof.verify_proof(&merkle_tree.merkle_root)? {
-        return Err("Generated Merkle proof failed verification".to_string());
-    }
-
-    env::log("Merkle proof generated and verified successfully");
-
-    let matching_tx = MatchingTransaction {
-        txid: pointing_proof.txid.clone(),
-        tx_type: actual_type,
-    };
-
-    Ok(BitcoinBlockProof {
-        block_hash: block_hash.to_string(),
-        block_height,
-        strategy: ProofStrategy::Pointing(pointing_proof.clone()),
-        matching_transactions: vec![matching_tx],
-        merkle_proofs: vec![merkle_proof],
-        total_transactions: block.txdata.len() as u32,
-        matching_count: 1,
-    })
 }
 
-/// Checks if a transaction matches Core Lane patterns and returns the type and txid if it does
-fn check_transaction_patterns(
-    tx: &Transaction,
-    _index: u32,
-    patterns: &CoreLanePatterns,
-    search_pattern: &TransactionPattern,
-) -> Result<Option<(TransactionType, String)>, String> {
-    let txid = tx.compute_txid().to_string();
-
-    // Check for burn transactions (OP_RETURN with BRN1 prefix)
-    if extract_burn_transaction(tx, patterns) {
-        if matches!(
-            search_pattern,
-            TransactionPattern::Burns | TransactionPattern::All
-        ) {
-            return Ok(Some((TransactionType::Burn, txid)));
-        }
-    }
-
-    // Check for Core Lane DA transactions
-    if extract_da_transaction(tx, patterns) {
-        if matches!(
-            search_pattern,
-            TransactionPattern::DataAvailability | TransactionPattern::All
-        ) {
-            return Ok(Some((TransactionType::DataAvailability, txid)));
-        }
-    }
-
-    // Check for fill transactions
-    if extract_fill_transaction(tx, patterns) {
-        if matches!(
-            search_pattern,
-            TransactionPattern::Fills | TransactionPattern::All
-        ) {
-            return Ok(Some((TransactionType::Fill, txid)));
-        }
-    }
-
-    Ok(None)
+/// Per-strategy matching state while transactions stream past
+enum Matcher<'a> {
+    /// Find transactions by pattern
+    Searching(&'a SearchingProof),
+    /// Prove a specific transaction exists
+    Pointing {
+        pointing_proof: &'a PointingProof,
+        target: [u8; 32],
+        /// Set once the target is seen; rejections wait for the Merkle root check
+        outcome: Option<Result<(), ProofError>>,
+    },
 }
 
-/// Checks if transaction is a burn transaction (OP_RETURN with BRN1 prefix)
-fn extract_burn_transaction(tx: &Transaction, patterns: &CoreLanePatterns) -> bool {
-    for output in &tx.output {
-        if output.script_pubkey.is_op_return() {
-            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
-                if payload.len() >= 28 && payload.starts_with(&patterns.burn_prefix) {
-                    return true;
-                }
+impl Matcher<'_> {
+    /// Returns the transaction type if this transaction should be committed
+    fn check(
+        &mut self,
+        tx: &Transaction,
+        txid: &[u8; 32],
+        index: u32,
+        patterns: &CoreLanePatterns,
+    ) -> Option<TransactionType> {
+        match self {
+            Matcher::Searching(searching_proof) => {
+                check_transaction_patterns(tx, index, patterns, &searching_proof.pattern)
             }
-        }
-    }
-    false
-}
-
-/// Checks if transaction is a Core Lane DA transaction
-fn extract_da_transaction(tx: &Transaction, patterns: &CoreLanePatterns) -> bool {
-    for output in &tx.output {
-        if output.script_pubkey.is_op_return() {
-            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
-                if payload.starts_with(&patterns.da_prefix) {
-                    return true;
+            Matcher::Pointing {
+                pointing_proof,
+                target,
+                outcome,
+            } => {
+                // Find the transaction by ID (ignore the provided position for now)
+                if txid != target || outcome.is_some() {
+                    return None;
                 }
-            }
-        }
-    }
-    false
-}
-
-/// Extracts data from OP_RETURN script
-fn extract_op_return_data(script: &bitcoin::Script) -> Option<Vec<u8>> {
-    let instructions = script.instructions();
-    for instruction in instructions {
-        if let Ok(bitcoin::script::Instruction::PushBytes(bytes)) = instruction {
-            return Some(bytes.as_bytes().to_vec());
-        }
-    }
-    None
-}
-
-/// Checks if transaction is a fill transaction (intent fulfillment)
-fn extract_fill_transaction(tx: &Transaction, _patterns: &CoreLanePatterns) -> bool {
-    // Fill transactions are identified by:
-    // 1. They send Bitcoin to a specific address (from intent)
-    // 2. They have a specific amount (from intent)
-    // 3. They may have OP_RETURN data indicating it's a fill
-
-    // For now, we'll identify fills by looking for OP_RETURN with "FILL" prefix
-    // In practice, fills would be identified by the filler bot pointing to them
-    for output in &tx.output {
-        if output.script_pubkey.is_op_return() {
-            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
-                if payload.len() >= 4 && payload.starts_with(b"FILL") {
-                    return true;
+                env::log(&format!("Found transaction at position {}", index));
+
+                // Verify the transaction matches the expected type
+                let Some(actual_type) =
+                    check_transaction_patterns(tx, index, patterns, &TransactionPattern::All)
+                else {
+                    *outcome = Some(Err(ProofError::NotCoreLaneTransaction {
+                        txid: pointing_proof.txid.clone(),
+                    }));
+                    return None;
+                };
+
+                if actual_type != pointing_proof.expected_type {
+                    *outcome = Some(Err(ProofError::TypeMismatch {
+                        txid: pointing_proof.txid.clone(),
+                        expected: pointing_proof.expected_type.clone(),
+                        actual: actual_type,
+                    }));
+                    return None;
                 }
+
+                *outcome = Some(Ok(()));
+                Some(actual_type)
             }
         }
     }
-
-    false
 }
 
 /// Calculates the burn amount from transaction inputs
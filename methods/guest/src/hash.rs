@@ -0,0 +1,74 @@
+use bitcoin::block::Header;
+use bitcoin::consensus::Encodable;
+use bitcoin::Transaction;
+use risc0_zkvm::sha::rust_crypto::{Digest as _, Sha256};
+
+/// Adapter that lets `consensus_encode` stream straight into the SHA-256 accelerator
+/// instead of serializing into an intermediate buffer first
+struct HashWriter(Sha256);
+
+impl bitcoin::io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> bitcoin::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> bitcoin::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HashWriter {
+    fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    /// Finish hashing and apply the second SHA-256 round used throughout Bitcoin
+    fn finalize_double(self) -> [u8; 32] {
+        let first: [u8; 32] = self.0.finalize().into();
+        sha256(&first)
+    }
+}
+
+/// Single SHA-256 using the risc0 accelerator
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Hashes two child nodes into their Merkle parent (double SHA-256, as in block headers)
+pub fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    let first: [u8; 32] = hasher.finalize().into();
+    sha256(&first)
+}
+
+/// Computes a transaction's txid (internal byte order)
+///
+/// Mirrors `Transaction::compute_txid`: the legacy serialization without witness data.
+pub fn txid(tx: &Transaction) -> [u8; 32] {
+    let mut writer = HashWriter::new();
+    tx.version
+        .consensus_encode(&mut writer)
+        .expect("hash engines don't error");
+    tx.input
+        .consensus_encode(&mut writer)
+        .expect("hash engines don't error");
+    tx.output
+        .consensus_encode(&mut writer)
+        .expect("hash engines don't error");
+    tx.lock_time
+        .consensus_encode(&mut writer)
+        .expect("hash engines don't error");
+    writer.finalize_double()
+}
+
+/// Computes a block hash from its header (internal byte order)
+pub fn block_hash(header: &Header) -> [u8; 32] {
+    let mut writer = HashWriter::new();
+    header
+        .consensus_encode(&mut writer)
+        .expect("hash engines don't error");
+    writer.finalize_double()
+}
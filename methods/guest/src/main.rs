@@ -1,19 +1,31 @@
+use std::io::BufReader;
+
 use risc0_zkvm::guest::env;
 
 mod bitcoin_processor;
+mod hash;
+mod matching;
 mod merkle_simple;
+mod multiproof;
+mod signet;
 mod types;
 
 use bitcoin_processor::process_bitcoin_block;
-use types::BitcoinBlockInput;
+use types::{BitcoinBlockInput, BlockProofJournal};
 
 fn main() {
     // Read the Bitcoin block input
     let input: BitcoinBlockInput = env::read();
 
+    // The raw block follows on stdin and is decoded as a stream
+    let mut reader = bitcoin::io::FromStd::new(BufReader::new(env::stdin()));
+
     // Process the Bitcoin block and extract Core Lane transactions
-    let proof = process_bitcoin_block(&input).expect("Failed to process Bitcoin block");
+    let journal: BlockProofJournal = process_bitcoin_block(&input, &mut reader);
+    if let Err(failure) = &journal {
+        env::log(&format!("Block rejected: {:?}", failure.error));
+    }
 
-    // Commit the proof to the journal
-    env::commit(&proof);
+    // Commit the proof, or the reason the block was rejected, to the journal
+    env::commit(&journal);
 }
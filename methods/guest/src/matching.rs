@@ -0,0 +1,109 @@
+//! Core Lane transaction matching
+//!
+//! Shared by the guest and, through `methods::matching`, by the host's native
+//! pre-scan, so both classify transactions identically. Must stay free of zkVM APIs.
+
+use bitcoin::Transaction;
+
+use crate::types::{CoreLanePatterns, TransactionPattern, TransactionType};
+
+/// Checks if a transaction matches Core Lane patterns and returns the type if it does
+pub fn check_transaction_patterns(
+    tx: &Transaction,
+    _index: u32,
+    patterns: &CoreLanePatterns,
+    search_pattern: &TransactionPattern,
+) -> Option<TransactionType> {
+    // Check for burn transactions (OP_RETURN with BRN1 prefix)
+    if extract_burn_transaction(tx, patterns)
+        && matches!(
+            search_pattern,
+            TransactionPattern::Burns | TransactionPattern::All
+        )
+    {
+        return Some(TransactionType::Burn);
+    }
+
+    // Check for Core Lane DA transactions
+    if extract_da_transaction(tx, patterns)
+        && matches!(
+            search_pattern,
+            TransactionPattern::DataAvailability | TransactionPattern::All
+        )
+    {
+        return Some(TransactionType::DataAvailability);
+    }
+
+    // Check for fill transactions
+    if extract_fill_transaction(tx, patterns)
+        && matches!(
+            search_pattern,
+            TransactionPattern::Fills | TransactionPattern::All
+        )
+    {
+        return Some(TransactionType::Fill);
+    }
+
+    None
+}
+
+/// Checks if transaction is a burn transaction (OP_RETURN with BRN1 prefix)
+fn extract_burn_transaction(tx: &Transaction, patterns: &CoreLanePatterns) -> bool {
+    for output in &tx.output {
+        if output.script_pubkey.is_op_return() {
+            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
+                if payload.len() >= 28 && payload.starts_with(&patterns.burn_prefix) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Checks if transaction is a Core Lane DA transaction
+fn extract_da_transaction(tx: &Transaction, patterns: &CoreLanePatterns) -> bool {
+    for output in &tx.output {
+        if output.script_pubkey.is_op_return() {
+            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
+                if payload.starts_with(&patterns.da_prefix) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Extracts data from OP_RETURN script
+fn extract_op_return_data(script: &bitcoin::Script) -> Option<Vec<u8>> {
+    let instructions = script.instructions();
+    for instruction in instructions {
+        if let Ok(bitcoin::script::Instruction::PushBytes(bytes)) = instruction {
+            return Some(bytes.as_bytes().to_vec());
+        }
+    }
+    None
+}
+
+/// Checks if transaction is a fill transaction (intent fulfillment)
+fn extract_fill_transaction(tx: &Transaction, _patterns: &CoreLanePatterns) -> bool {
+    // Fill transactions are identified by:
+    // 1. They send Bitcoin to a specific address (from intent)
+    // 2. They have a specific amount (from intent)
+    // 3. They may have OP_RETURN data indicating it's a fill
+
+    // For now, we'll identify fills by looking for OP_RETURN with "FILL" prefix
+    // In practice, fills would be identified by the filler bot pointing to them
+    for output in &tx.output {
+        if output.script_pubkey.is_op_return() {
+            if let Some(payload) = extract_op_return_data(&output.script_pubkey) {
+                if payload.len() >= 4 && payload.starts_with(b"FILL") {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
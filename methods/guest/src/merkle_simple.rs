@@ -1,4 +1,5 @@
-use bitcoin::hashes::{sha256, Hash, HashEngine};
+use crate::hash::merkle_parent;
+use crate::multiproof::MerkleMultiproof;
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct MerkleProof {
@@ -7,145 +8,421 @@ pub struct MerkleProof {
     pub positions: Vec<bool>, // true for right child, false for left child
 }
 
+/// Root of a complete subtree waiting for its right sibling
 #[derive(Debug, Clone)]
-pub struct MerkleTree {
-    pub leaves: Vec<[u8; 32]>,
-    pub merkle_root: [u8; 32],
-    pub tree: Vec<Vec<[u8; 32]>>, // Stores all levels of the tree
+struct PendingNode {
+    hash: [u8; 32],
+    index: u32,    // Position among the nodes of its level
+    tracked: bool, // Whether a tracked leaf is below this node
 }
 
-impl MerkleTree {
-    pub fn build_merkle_tree(txids: &[[u8; 32]]) -> Result<Self, String> {
-        if txids.is_empty() {
-            return Err("Cannot build Merkle tree from empty transaction list".to_string());
-        }
+/// Streaming Bitcoin Merkle root computation
+///
+/// Leaves are pushed one at a time and combined as soon as their sibling is known, so
+/// memory is one pending node per tree level instead of the whole tree. Leaves pushed
+/// with `track = true` get a multiproof collected along the way, which only keeps the
+/// sibling hashes their paths need.
+#[derive(Debug, Default)]
+pub struct MerkleAccumulator {
+    levels: Vec<Option<PendingNode>>, // levels[i] holds a subtree of 2^i leaves
+    tracked_indices: Vec<u32>,
+    multiproof_hashes: Vec<(usize, u32, [u8; 32])>, // (level, index, hash), any order
+    leaf_count: u32,
+    /// Level at which two identical siblings were combined, if any
+    mutated_at: Option<usize>,
+}
+
+impl MerkleAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn leaf_count(&self) -> u32 {
+        self.leaf_count
+    }
 
-        let mut leaves = txids.to_vec();
-        if leaves.len() % 2 != 0 {
-            leaves.push(*leaves.last().unwrap()); // Duplicate last leaf if odd number
+    /// Adds the next leaf (txid in internal byte order)
+    pub fn push(&mut self, txid: [u8; 32], track: bool) {
+        let mut carry = PendingNode {
+            hash: txid,
+            index: self.leaf_count,
+            tracked: track,
+        };
+        if track {
+            self.tracked_indices.push(self.leaf_count);
         }
+        self.leaf_count += 1;
 
-        let mut tree = Vec::new();
-        tree.push(leaves.clone());
+        let mut level = 0;
+        loop {
+            if level == self.levels.len() {
+                self.levels.push(None);
+            }
+            match self.levels[level].take() {
+                Some(left) => {
+                    carry = self.combine(level, left, carry, false);
+                    level += 1;
+                }
+                None => {
+                    self.levels[level] = Some(carry);
+                    return;
+                }
+            }
+        }
+    }
 
-        let mut current_level = leaves;
+    /// Computes the Merkle root and returns it with a multiproof covering the tracked
+    /// leaves (if any)
+    ///
+    /// Follows Bitcoin's rule of duplicating the last node of any level with an odd count.
+    /// Fails if two identical siblings were combined anywhere else: such a transaction
+    /// list is mutated (CVE-2012-2459), e.g. a valid block with its last transactions
+    /// repeated, which hashes to the same root as the original.
+    pub fn finalize(mut self) -> Result<([u8; 32], Option<MerkleMultiproof>), String> {
+        let lowest = self
+            .levels
+            .iter()
+            .position(Option::is_some)
+            .ok_or("Cannot build Merkle tree from empty transaction list")?;
+        let top = self.levels.len() - 1;
 
-        while current_level.len() > 1 {
-            let mut next_level = Vec::new();
-            for i in (0..current_level.len()).step_by(2) {
-                let left = current_level[i];
-                let right = if i + 1 < current_level.len() {
-                    current_level[i + 1]
-                } else {
-                    current_level[i] // Should not happen with initial padding
+        let mut carry = self.levels[lowest].take().unwrap();
+        if lowest < top {
+            // The lowest subtree is the last node of a level with an odd count
+            carry = self.duplicate(lowest, carry);
+            for level in lowest + 1..=top {
+                carry = match self.levels[level].take() {
+                    Some(left) => self.combine(level, left, carry, false),
+                    None => self.duplicate(level, carry),
                 };
-
-                let mut engine = sha256::HashEngine::default();
-                engine.input(&left);
-                engine.input(&right);
-                let hash = sha256::Hash::from_engine(engine).to_byte_array();
-                next_level.push(hash);
             }
-            current_level = next_level;
-            tree.push(current_level.clone());
         }
 
-        let merkle_root = *current_level.first().unwrap();
+        if let Some(level) = self.mutated_at {
+            return Err(format!(
+                "Merkle tree is mutated: identical siblings at level {}",
+                level
+            ));
+        }
+        let multiproof = if self.tracked_indices.is_empty() {
+            None
+        } else {
+            self.multiproof_hashes
+                .sort_unstable_by_key(|&(level, index, _)| (level, index));
+            Some(MerkleMultiproof {
+                leaf_count: self.leaf_count,
+                indices: self.tracked_indices,
+                hashes: self
+                    .multiproof_hashes
+                    .into_iter()
+                    .map(|(_, _, hash)| hash)
+                    .collect(),
+            })
+        };
+        Ok((carry.hash, multiproof))
+    }
 
-        Ok(Self {
-            leaves: txids.to_vec(), // Store original leaves
-            merkle_root,
-            tree,
-        })
+    /// Pairs the last node of an odd-sized level with a copy of itself
+    fn duplicate(&mut self, level: usize, node: PendingNode) -> PendingNode {
+        let copy = PendingNode {
+            hash: node.hash,
+            index: node.index + 1,
+            tracked: false,
+        };
+        self.combine(level, node, copy, true)
     }
 
-    pub fn generate_proof(&self, tx_index: u32) -> Result<MerkleProof, String> {
-        if tx_index as usize >= self.leaves.len() {
-            return Err("Transaction index out of bounds".to_string());
+    /// Hashes two nodes on `level` into their parent
+    ///
+    /// A sibling of a subtree with tracked leaves goes into the multiproof unless it has
+    /// tracked leaves itself (its hash is then computed) or is a duplicate (verifiers
+    /// duplicate on their own). Only `duplicate` may pair a node with itself; identical
+    /// siblings otherwise mean repeated transactions (like Bitcoin Core's `mutated` flag).
+    fn combine(
+        &mut self,
+        level: usize,
+        left: PendingNode,
+        right: PendingNode,
+        duplicated: bool,
+    ) -> PendingNode {
+        if !duplicated && left.hash == right.hash && self.mutated_at.is_none() {
+            self.mutated_at = Some(level);
+        }
+        match (left.tracked, right.tracked) {
+            (true, false) if !duplicated => {
+                self.multiproof_hashes
+                    .push((level, right.index, right.hash))
+            }
+            (false, true) => self.multiproof_hashes.push((level, left.index, left.hash)),
+            _ => {}
         }
 
-        let original_txid = self.leaves[tx_index as usize];
-        let mut current_hash = original_txid;
-        let mut path = Vec::new();
-        let mut positions = Vec::new();
-
-        let mut current_level_index = 0;
-        let mut current_tx_index = tx_index as usize;
+        PendingNode {
+            hash: merkle_parent(&left.hash, &right.hash),
+            index: left.index / 2,
+            tracked: left.tracked || right.tracked,
+        }
+    }
+}
 
-        while current_level_index < self.tree.len() - 1 {
-            let level = &self.tree[current_level_index];
-            let sibling_index;
-            let is_right_sibling;
+impl MerkleProof {
+    pub fn verify_proof(&self, merkle_root: &[u8; 32]) -> Result<bool, String> {
+        let mut current_hash = self.txid;
 
-            if current_tx_index % 2 == 0 {
-                // Current hash is a left child
-                sibling_index = current_tx_index + 1;
-                is_right_sibling = true;
-            } else {
-                // Current hash is a right child
-                sibling_index = current_tx_index - 1;
-                is_right_sibling = false;
-            }
+        for (i, sibling_hash) in self.path.iter().enumerate() {
+            let is_right_sibling = self.positions[i];
 
-            let sibling_hash = if sibling_index < level.len() {
-                level[sibling_index]
+            current_hash = if is_right_sibling {
+                merkle_parent(&current_hash, sibling_hash)
             } else {
-                // This case should only happen if the last element was duplicated
-                // and we are processing the duplicated element.
-                // In a canonical Merkle tree, this means the sibling is itself.
-                level[current_tx_index]
+                merkle_parent(sibling_hash, &current_hash)
             };
+        }
+
+        Ok(&current_hash == merkle_root)
+    }
+}
 
-            path.push(sibling_hash);
-            positions.push(is_right_sibling);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash;
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Block, TxMerkleNode, Txid};
 
-            // Compute parent hash
-            let mut engine = sha256::HashEngine::default();
-            if is_right_sibling {
-                engine.input(&current_hash);
-                engine.input(&sibling_hash);
-            } else {
-                engine.input(&sibling_hash);
-                engine.input(&current_hash);
+    /// Mainnet block 00000000b0c5a240b2a61d2e75692224efd4cbecdf6eaf4cc2cf477ca7c270e7
+    /// (two transactions)
+    const MAINNET_BLOCK: &str = concat!(
+        "010000004ddccd549d28f385ab457e98d1b11ce80bfea2c5ab93015ade4973e4",
+        "00000000bf4473e53794beae34e64fccc471dace6ae544180816f89591894e0f",
+        "417a914cd74d6e49ffff001d323b3a7b02010000000100000000000000000000",
+        "00000000000000000000000000000000000000000000ffffffff0804ffff001d",
+        "026e04ffffffff0100f2052a0100000043410446ef0102d1ec5240f0d061a424",
+        "6c1bdef63fc3dbab7733052fbbf0ecd8f41fc26bf049ebb4f9527f374280259e",
+        "7cfa99c48b0e3f39c51347a19a5819651503a5ac00000000010000000321f75f",
+        "3139a013f50f315b23b0c9a2b6eac31e2bec98e5891c92466488994226000000",
+        "0049483045022100cb2c6b346a978ab8c61b18b5e9397755cbd17d6eb2fe0083",
+        "ef32e067fa6c785a02206ce44e613f31d9a6b0517e46f3db1576e9812cc98d15",
+        "9bfdaf759a5014081b5c01ffffffff79cda0945903627c3da1f85fc95d0b8ee3",
+        "e76ae0cfdc9a65d09744b1f8fc85430000000049483045022047957cdd957cfd",
+        "0becd642f6b84d82f49b6cb4c51a91f49246908af7c3cfdf4a022100e96b4662",
+        "1f1bffcf5ea5982f88cef651e9354f5791602369bf5a82a6cd61a62501ffffff",
+        "fffe09f5fe3ffbf5ee97a54eb5e5069e9da6b4856ee86fc52938c2f979b0f38e",
+        "82000000004847304402204165be9a4cbab8049e1af9723b96199bfd3e85f44c",
+        "6b4c0177e3962686b26073022028f638da23fc003760861ad481ead4099312c6",
+        "0030d4cb57820ce4d33812a5ce01ffffffff01009d966b01000000434104ea1f",
+        "eff861b51fe3f5f8a3b12d0f4712db80e919548a80839fc47c6a21e66d957e9c",
+        "5d8cd108c7a2d2324bad71f9904ac0ae7336507d785b17a2c115e427a32fac00",
+        "000000",
+    );
+
+    fn root_of(txids: &[[u8; 32]]) -> [u8; 32] {
+        let mut accumulator = MerkleAccumulator::new();
+        for txid in txids {
+            accumulator.push(*txid, false);
+        }
+        accumulator.finalize().unwrap().0
+    }
+
+    #[test]
+    fn test_merkle_root_matches_mainnet_header() {
+        let block: Block = deserialize(&hex::decode(MAINNET_BLOCK).unwrap()).unwrap();
+        let txids: Vec<[u8; 32]> = block.txdata.iter().map(hash::txid).collect();
+
+        assert_eq!(
+            merkle_parent(&txids[0], &txids[1]),
+            block.header.merkle_root.to_byte_array()
+        );
+        assert_eq!(root_of(&txids), block.header.merkle_root.to_byte_array());
+    }
+
+    fn bitcoin_root(txids: &[[u8; 32]]) -> [u8; 32] {
+        bitcoin::merkle_tree::calculate_root(txids.iter().map(|txid| Txid::from_byte_array(*txid)))
+            .map(TxMerkleNode::from)
+            .unwrap()
+            .to_byte_array()
+    }
+
+    #[test]
+    fn test_merkle_root_matches_bitcoin_crate() {
+        // Odd counts exercise the duplication of the last node on each level
+        for count in 1..=17u8 {
+            let txids: Vec<[u8; 32]> = (0..count).map(|i| [i; 32]).collect();
+            assert_eq!(root_of(&txids), bitcoin_root(&txids), "{} leaves", count);
+        }
+    }
+
+    #[test]
+    fn test_merkle_accumulator_rejects_duplicated_tail() {
+        // [a, b, c] and [a, b, c, c] share a root, as do [a..f] and [a..f, e, f]
+        for (count, repeated) in [(3u8, 1usize), (6, 2), (5, 1)] {
+            let txids: Vec<[u8; 32]> = (0..count).map(|i| [i; 32]).collect();
+            let mut mutated = txids.clone();
+            mutated.extend_from_slice(&txids[txids.len() - repeated..]);
+            assert_eq!(root_of(&txids), bitcoin_root(&mutated), "{} leaves", count);
+
+            let mut accumulator = MerkleAccumulator::new();
+            for txid in &mutated {
+                accumulator.push(*txid, false);
             }
-            current_hash = sha256::Hash::from_engine(engine).to_byte_array();
+            let error = accumulator.finalize().unwrap_err();
+            assert!(error.contains("mutated"), "{}", error);
+        }
+    }
 
-            current_level_index += 1;
-            current_tx_index /= 2;
+    #[test]
+    fn test_merkle_accumulator_empty() {
+        assert!(MerkleAccumulator::new().finalize().is_err());
+    }
+
+    fn leaves(count: u32) -> Vec<[u8; 32]> {
+        (0..count).map(|i| [i as u8 + 1; 32]).collect()
+    }
+
+    /// Root and multiproof of `count` leaves with `tracked` ones proven, and the proven
+    /// leaves
+    fn multiproof_of(count: u32, tracked: &[u32]) -> ([u8; 32], MerkleMultiproof, Vec<[u8; 32]>) {
+        let txids = leaves(count);
+        let mut accumulator = MerkleAccumulator::new();
+        for (index, txid) in txids.iter().enumerate() {
+            accumulator.push(*txid, tracked.contains(&(index as u32)));
         }
+        let (root, multiproof) = accumulator.finalize().unwrap();
+        let proven = tracked.iter().map(|&index| txids[index as usize]).collect();
+        (root, multiproof.unwrap(), proven)
+    }
 
-        if current_hash != self.merkle_root {
-            return Err(
-                "Merkle proof generation failed: final hash does not match root".to_string(),
-            );
+    /// Path of leaf `index`, from the whole tree
+    fn full_path(txids: &[[u8; 32]], mut index: usize) -> MerkleProof {
+        let mut proof = MerkleProof {
+            txid: txids[index],
+            path: Vec::new(),
+            positions: Vec::new(),
+        };
+        let mut level = txids.to_vec();
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+            proof.path.push(level[index ^ 1]);
+            proof.positions.push(index.is_multiple_of(2));
+            level = level
+                .chunks(2)
+                .map(|pair| merkle_parent(&pair[0], &pair[1]))
+                .collect();
+            index /= 2;
         }
+        proof
+    }
 
-        Ok(MerkleProof {
-            txid: original_txid,
-            path,
-            positions,
-        })
+    /// Checks the root and every path the multiproof yields against the whole tree
+    fn check_multiproof(count: u32, tracked: &[u32]) -> MerkleMultiproof {
+        let (root, multiproof, proven) = multiproof_of(count, tracked);
+        assert_eq!(
+            multiproof.root(&proven),
+            Ok(root),
+            "{:?} of {}",
+            tracked,
+            count
+        );
+        assert!(multiproof.verify(&proven, &root));
+        for (&index, leaf) in tracked.iter().zip(&proven) {
+            let proof = multiproof.proof_for(&proven, leaf).unwrap().unwrap();
+            assert_eq!(proof, full_path(&leaves(count), index as usize));
+            assert!(proof.verify_proof(&root).unwrap());
+        }
+        multiproof
     }
-}
 
-impl MerkleProof {
-    pub fn verify_proof(&self, merkle_root: &[u8; 32]) -> Result<bool, String> {
-        let mut current_hash = self.txid;
+    #[test]
+    fn test_multiproof_single_match() {
+        let multiproof = check_multiproof(8, &[5]);
+        // One sibling per level, as in a plain path
+        assert_eq!(multiproof.indices, [5]);
+        assert_eq!(multiproof.hashes.len(), 3);
+        assert_eq!(multiproof.hashes[0], leaves(8)[4]);
 
-        for (i, sibling_hash) in self.path.iter().enumerate() {
-            let is_right_sibling = self.positions[i];
-            let mut engine = sha256::HashEngine::default();
+        let (_, multiproof, proven) = multiproof_of(8, &[5]);
+        assert_eq!(multiproof.proof_for(&proven, &[0xaa; 32]), Ok(None));
+        check_multiproof(1, &[0]);
+    }
 
-            if is_right_sibling {
-                engine.input(&current_hash);
-                engine.input(sibling_hash);
-            } else {
-                engine.input(sibling_hash);
-                engine.input(&current_hash);
+    #[test]
+    fn test_multiproof_several_matches() {
+        let multiproof = check_multiproof(16, &[2, 3, 9]);
+        // 2 and 3 are siblings, and their parent is computed rather than sent
+        assert_eq!(multiproof.hashes.len(), 2 + 3);
+        check_multiproof(17, &[0, 1, 7, 8, 15, 16]);
+
+        let multiproof = check_multiproof(8, &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert!(multiproof.hashes.is_empty());
+
+        // Every set of matches in small trees
+        for count in 1..=9u32 {
+            for set in 1..1u32 << count {
+                let tracked: Vec<u32> = (0..count).filter(|i| set & (1 << i) != 0).collect();
+                check_multiproof(count, &tracked);
             }
-            current_hash = sha256::Hash::from_engine(engine).to_byte_array();
         }
+    }
 
-        Ok(&current_hash == merkle_root)
+    #[test]
+    fn test_multiproof_duplicated_last_node() {
+        // Leaf 4 of 5 is paired with itself on the two lowest levels
+        let multiproof = check_multiproof(5, &[4]);
+        assert_eq!(multiproof.hashes.len(), 1);
+        let (_, multiproof, proven) = multiproof_of(5, &[4]);
+        let proof = multiproof.proof_for(&proven, &proven[0]).unwrap().unwrap();
+        assert_eq!(proof.path[0], proven[0]);
+        assert_eq!(proof.positions, [true, true, false]);
+
+        // Leaf 2 of 3 is paired with itself, so only leaf 1 is sent
+        let multiproof = check_multiproof(3, &[0, 2]);
+        assert_eq!(multiproof.hashes, [leaves(3)[1]]);
+        check_multiproof(6, &[5]);
+    }
+
+    #[test]
+    fn test_multiproof_tampered() {
+        let (root, multiproof, proven) = multiproof_of(11, &[1, 6, 10]);
+        assert!(multiproof.verify(&proven, &root));
+
+        for sibling in 0..multiproof.hashes.len() {
+            let mut tampered = multiproof.clone();
+            tampered.hashes[sibling][0] ^= 1;
+            assert!(!tampered.verify(&proven, &root), "sibling {}", sibling);
+            let proof = tampered.proof_for(&proven, &proven[1]).unwrap().unwrap();
+            // Every sibling sent feeds into the path of each match
+            assert!(!proof.verify_proof(&root).unwrap(), "sibling {}", sibling);
+        }
+
+        let mut swapped = proven.clone();
+        swapped.swap(0, 1);
+        assert!(!multiproof.verify(&swapped, &root));
+        assert!(!multiproof.verify(&leaves(3), &root));
+
+        let mut extra = multiproof.clone();
+        extra.hashes.push([0; 32]);
+        assert_eq!(
+            extra.root(&proven),
+            Err("Multiproof has unused hashes".to_string())
+        );
+        let mut missing = multiproof.clone();
+        missing.hashes.pop();
+        assert_eq!(
+            missing.root(&proven),
+            Err("Multiproof is missing hashes".to_string())
+        );
+        let mut unordered = multiproof.clone();
+        unordered.indices.swap(0, 1);
+        assert_eq!(
+            unordered.root(&proven),
+            Err("Multiproof positions are out of order or range".to_string())
+        );
+        assert_eq!(
+            multiproof.root(&proven[..2]),
+            Err("Multiproof covers 3 transactions, got 2".to_string())
+        );
     }
 }
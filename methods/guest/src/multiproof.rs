@@ -0,0 +1,146 @@
+//! Merkle multiproofs over the matching transactions of a block
+//!
+//! Shared by the guest, which verifies the multiproof it commits, and through
+//! `methods::multiproof` by the host, which turns it back into per-transaction paths.
+//! Must stay free of zkVM APIs; `crate::hash::merkle_parent` is provided by both.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hash::merkle_parent;
+use crate::types::MerkleProof;
+
+/// Inclusion proof for several transactions at once, sharing the interior nodes of
+/// their paths
+///
+/// The proven leaves are the matching transactions themselves, so only their
+/// positions are stored here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleMultiproof {
+    /// Number of transactions in the block
+    pub leaf_count: u32,
+    /// Positions of the proven transactions in the block, ascending
+    pub indices: Vec<u32>,
+    /// Hashes that can't be computed from the proven transactions, bottom level first
+    /// and left to right within a level
+    pub hashes: Vec<[u8; 32]>,
+}
+
+impl MerkleMultiproof {
+    /// Whether the proven leaves (txids in `indices` order) hash up to `merkle_root`
+    pub fn verify(&self, leaves: &[[u8; 32]], merkle_root: &[u8; 32]) -> bool {
+        self.root(leaves).is_ok_and(|root| &root == merkle_root)
+    }
+
+    /// Recomputes the Merkle root from the proven leaves (txids in internal byte
+    /// order, in `indices` order), to compare with a block header's
+    pub fn root(&self, leaves: &[[u8; 32]]) -> Result<[u8; 32], String> {
+        let mut levels = self.levels(leaves)?;
+        Ok(levels
+            .pop()
+            .and_then(|mut top| top.remove(&0))
+            .unwrap_or_default())
+    }
+
+    /// Single-transaction inclusion proof for one of the proven leaves
+    pub fn proof_for(
+        &self,
+        leaves: &[[u8; 32]],
+        txid: &[u8; 32],
+    ) -> Result<Option<MerkleProof>, String> {
+        let Some(position) = leaves.iter().position(|leaf| leaf == txid) else {
+            return Ok(None);
+        };
+        let levels = self.levels(leaves)?;
+
+        let mut index = self.indices[position];
+        let mut proof = MerkleProof {
+            txid: *txid,
+            path: Vec::new(),
+            positions: Vec::new(),
+        };
+        for level in &levels[..levels.len() - 1] {
+            let is_right_sibling = index.is_multiple_of(2);
+            let sibling = if is_right_sibling {
+                index + 1
+            } else {
+                index - 1
+            };
+            // A missing right sibling is the duplicated last node of the level
+            let hash = level.get(&sibling).or_else(|| level.get(&index));
+            proof
+                .path
+                .push(*hash.ok_or("Multiproof is missing a sibling")?);
+            proof.positions.push(is_right_sibling);
+            index /= 2;
+        }
+        Ok(Some(proof))
+    }
+
+    /// Every node the proof determines, level by level from the leaves to the root
+    fn levels(&self, leaves: &[[u8; 32]]) -> Result<Vec<BTreeMap<u32, [u8; 32]>>, String> {
+        if leaves.is_empty() || leaves.len() != self.indices.len() {
+            return Err(format!(
+                "Multiproof covers {} transactions, got {}",
+                self.indices.len(),
+                leaves.len()
+            ));
+        }
+        if self.indices.windows(2).any(|pair| pair[0] >= pair[1])
+            || self
+                .indices
+                .last()
+                .is_some_and(|&last| last >= self.leaf_count)
+        {
+            return Err("Multiproof positions are out of order or range".to_string());
+        }
+
+        let mut hashes = self.hashes.iter();
+        let mut next_hash = || hashes.next().copied().ok_or("Multiproof is missing hashes");
+        let mut levels = Vec::new();
+        let mut level: BTreeMap<u32, [u8; 32]> = self
+            .indices
+            .iter()
+            .copied()
+            .zip(leaves.iter().copied())
+            .collect();
+        let mut width = self.leaf_count;
+        while width > 1 {
+            let mut known = level.clone();
+            let mut parents = BTreeMap::new();
+            for (&index, hash) in &level {
+                if parents.contains_key(&(index / 2)) {
+                    continue; // Already hashed with its left sibling
+                }
+                let parent = if !index.is_multiple_of(2) {
+                    // A known left sibling would have been hashed first
+                    let left = next_hash()?;
+                    known.insert(index - 1, left);
+                    merkle_parent(&left, hash)
+                } else if index + 1 == width {
+                    merkle_parent(hash, hash)
+                } else {
+                    let right = match level.get(&(index + 1)) {
+                        Some(right) => *right,
+                        None => {
+                            let right = next_hash()?;
+                            known.insert(index + 1, right);
+                            right
+                        }
+                    };
+                    merkle_parent(hash, &right)
+                };
+                parents.insert(index / 2, parent);
+            }
+            levels.push(known);
+            level = parents;
+            width = width.div_ceil(2);
+        }
+        if next_hash().is_ok() {
+            return Err("Multiproof has unused hashes".to_string());
+        }
+        levels.push(level);
+        Ok(levels)
+    }
+}
@@ -0,0 +1,531 @@
+use bitcoin::block::Header;
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::{OP_CHECKMULTISIG, OP_CHECKSIG, OP_RETURN};
+use bitcoin::opcodes::OP_0;
+use bitcoin::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::sighash::SighashCache;
+use bitcoin::transaction::Version;
+use bitcoin::{
+    absolute, OutPoint, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+
+use crate::hash;
+use crate::merkle_simple::MerkleAccumulator;
+
+/// Marks the signet solution inside the witness commitment output (BIP325)
+const SIGNET_HEADER: [u8; 4] = [0xec, 0xc7, 0xda, 0xa2];
+
+/// Start of a witness commitment output: OP_RETURN, push 36, `aa21a9ed`
+const WITNESS_COMMITMENT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+/// scriptSig and witness extracted from the coinbase
+struct SignetSolution {
+    script_sig: ScriptBuf,
+    witness: Witness,
+}
+
+/// Streaming BIP325 block solution check
+///
+/// The signature commits to the block with the solution removed from the coinbase, so
+/// a second Merkle root is accumulated next to the block's: the modified coinbase
+/// followed by the remaining txids.
+///
+/// Supported challenges are the forms Bitcoin Core's signet tooling produces: bare
+/// `m`-of-`n` `OP_CHECKMULTISIG` (the default signet), `<pubkey> OP_CHECKSIG`, and
+/// `OP_TRUE`. Anything else, including witness programs, is rejected.
+pub struct SignetVerifier<'a> {
+    challenge: &'a Script,
+    solution: Option<Result<SignetSolution, String>>,
+    modified_merkle: MerkleAccumulator,
+}
+
+impl<'a> SignetVerifier<'a> {
+    pub fn new(challenge: &'a [u8]) -> Self {
+        Self {
+            challenge: Script::from_bytes(challenge),
+            solution: None,
+            modified_merkle: MerkleAccumulator::new(),
+        }
+    }
+
+    /// Adds the next transaction; the first one must be the coinbase
+    pub fn push(&mut self, tx: &Transaction, txid: [u8; 32]) {
+        if self.solution.is_some() {
+            self.modified_merkle.push(txid, false);
+            return;
+        }
+
+        let mut coinbase = tx.clone();
+        let solution = extract_solution(&mut coinbase);
+        self.modified_merkle.push(hash::txid(&coinbase), false);
+        self.solution = Some(solution);
+    }
+
+    /// Checks the solution against the challenge
+    pub fn verify(self, header: &Header) -> Result<(), String> {
+        let solution = self.solution.ok_or("Block has no coinbase")??;
+        let (signet_merkle, _) = self.modified_merkle.finalize()?;
+
+        // The challenge is "spent" by a virtual transaction committing to the header
+        // fields the signer saw
+        let mut block_data = Vec::with_capacity(72);
+        header
+            .version
+            .consensus_encode(&mut block_data)
+            .expect("in-memory writers don't error");
+        header
+            .prev_blockhash
+            .consensus_encode(&mut block_data)
+            .expect("in-memory writers don't error");
+        block_data.extend_from_slice(&signet_merkle);
+        header
+            .time
+            .consensus_encode(&mut block_data)
+            .expect("in-memory writers don't error");
+        let block_data = PushBytesBuf::try_from(block_data).expect("72 bytes fit in a push");
+
+        let to_spend = Transaction {
+            version: Version(0),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new()
+                    .push_opcode(OP_0)
+                    .push_slice(block_data)
+                    .into_script(),
+                sequence: Sequence(0),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: self.challenge.to_owned(),
+            }],
+        };
+        let spending = Transaction {
+            version: Version(0),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array(hash::txid(&to_spend)), 0),
+                script_sig: solution.script_sig,
+                sequence: Sequence(0),
+                witness: solution.witness,
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+
+        verify_challenge(self.challenge, &spending)
+    }
+}
+
+/// Removes the signet solution from the coinbase's witness commitment and decodes it
+///
+/// Mirrors Bitcoin Core's `FetchAndClearCommitmentSection`: the first push starting with
+/// the signet header (plus data) is cut back to just the header and the script is
+/// re-serialized. A block without a solution gets an empty one, which only `OP_TRUE`
+/// accepts.
+fn extract_solution(coinbase: &mut Transaction) -> Result<SignetSolution, String> {
+    let commitment = coinbase
+        .output
+        .iter()
+        .rposition(|output| {
+            let script = output.script_pubkey.as_bytes();
+            script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_PREFIX)
+        })
+        .ok_or("Coinbase has no witness commitment")?;
+
+    let script = &coinbase.output[commitment].script_pubkey;
+    let mut replacement = Builder::new();
+    let mut solution = None;
+    for instruction in script.instructions() {
+        let instruction =
+            instruction.map_err(|e| format!("Unparseable witness commitment: {}", e))?;
+        replacement = match instruction {
+            Instruction::PushBytes(data) if data.is_empty() => replacement.push_opcode(OP_0),
+            Instruction::PushBytes(data) => {
+                let data = data.as_bytes();
+                if solution.is_none()
+                    && data.len() > SIGNET_HEADER.len()
+                    && data.starts_with(&SIGNET_HEADER)
+                {
+                    solution = Some(data[SIGNET_HEADER.len()..].to_vec());
+                    replacement.push_slice(SIGNET_HEADER)
+                } else {
+                    let data = PushBytesBuf::try_from(data.to_vec())
+                        .map_err(|_| "Oversized push in witness commitment")?;
+                    replacement.push_slice(data)
+                }
+            }
+            Instruction::Op(opcode) => replacement.push_opcode(opcode),
+        };
+    }
+
+    let Some(solution) = solution else {
+        return Ok(SignetSolution {
+            script_sig: ScriptBuf::new(),
+            witness: Witness::new(),
+        });
+    };
+    coinbase.output[commitment].script_pubkey = replacement.into_script();
+
+    let mut reader = solution.as_slice();
+    let script_sig = ScriptBuf::consensus_decode(&mut reader)
+        .map_err(|e| format!("Unparseable signet scriptSig: {}", e))?;
+    let witness = Witness::consensus_decode(&mut reader)
+        .map_err(|e| format!("Unparseable signet witness: {}", e))?;
+    if !reader.is_empty() {
+        return Err("Extraneous data after the signet solution".to_string());
+    }
+    Ok(SignetSolution {
+        script_sig,
+        witness,
+    })
+}
+
+/// Evaluates the supported challenge forms against the spending transaction
+fn verify_challenge(challenge: &Script, spending: &Transaction) -> Result<(), String> {
+    let input = &spending.input[0];
+    if !input.witness.is_empty() {
+        return Err("Signet witness data is only valid for witness challenges".to_string());
+    }
+    let pushes = push_data(&input.script_sig)?;
+
+    let instructions = challenge
+        .instructions()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Unparseable signet challenge: {}", e))?;
+    match instructions.as_slice() {
+        // OP_TRUE: trivial challenge
+        [Instruction::Op(op)] if op.to_u8() == 0x51 => Ok(()),
+        // <pubkey> OP_CHECKSIG
+        [Instruction::PushBytes(pubkey), Instruction::Op(OP_CHECKSIG)] => {
+            let [signature] = pushes.as_slice() else {
+                return Err("Expected one signature for a single-key challenge".to_string());
+            };
+            if check_signature(challenge, spending, signature, pubkey.as_bytes()) {
+                Ok(())
+            } else {
+                Err("Signet signature does not verify".to_string())
+            }
+        }
+        // OP_m <pubkey>... OP_n OP_CHECKMULTISIG
+        [Instruction::Op(m), pubkeys @ .., Instruction::Op(n), Instruction::Op(OP_CHECKMULTISIG)] =>
+        {
+            let (Some(m), Some(n)) = (small_int(m.to_u8()), small_int(n.to_u8())) else {
+                return Err("Unsupported signet challenge".to_string());
+            };
+            if pubkeys.len() != n || m > n {
+                return Err("Malformed multisig signet challenge".to_string());
+            }
+            // CHECKMULTISIG pops an extra item, which must be empty (NULLDUMMY)
+            let Some((dummy, signatures)) = pushes.split_first() else {
+                return Err("Empty signet scriptSig".to_string());
+            };
+            if !dummy.is_empty() || signatures.len() != m {
+                return Err(format!(
+                    "Expected OP_0 and {} signatures for a {}-of-{} challenge",
+                    m, m, n
+                ));
+            }
+
+            // Signatures must match keys in order, each key used at most once
+            let mut keys = pubkeys.iter();
+            for signature in signatures {
+                let matched = keys.by_ref().any(|pubkey| match pubkey {
+                    Instruction::PushBytes(pubkey) => {
+                        check_signature(challenge, spending, signature, pubkey.as_bytes())
+                    }
+                    Instruction::Op(_) => false,
+                });
+                if !matched {
+                    return Err("Signet multisig signatures do not verify".to_string());
+                }
+            }
+            Ok(())
+        }
+        _ => Err("Unsupported signet challenge".to_string()),
+    }
+}
+
+/// The data pushes of a push-only scriptSig
+fn push_data(script_sig: &Script) -> Result<Vec<Vec<u8>>, String> {
+    script_sig
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(data)) => Ok(data.as_bytes().to_vec()),
+            Ok(Instruction::Op(_)) => Err("Signet scriptSig must be push-only".to_string()),
+            Err(e) => Err(format!("Unparseable signet scriptSig: {}", e)),
+        })
+        .collect()
+}
+
+/// Value of OP_1..OP_16
+fn small_int(opcode: u8) -> Option<usize> {
+    (0x51..=0x60)
+        .contains(&opcode)
+        .then(|| (opcode - 0x50) as usize)
+}
+
+/// Checks one DER signature (with sighash byte) over the legacy sighash
+fn check_signature(
+    challenge: &Script,
+    spending: &Transaction,
+    signature: &[u8],
+    pubkey: &[u8],
+) -> bool {
+    let (Ok(mut signature), Ok(pubkey)) = (
+        bitcoin::ecdsa::Signature::from_slice(signature),
+        PublicKey::from_slice(pubkey),
+    ) else {
+        return false;
+    };
+    let Ok(sighash) = SighashCache::new(spending).legacy_signature_hash(
+        0,
+        challenge,
+        signature.sighash_type.to_u32(),
+    ) else {
+        return false;
+    };
+
+    // Consensus accepts high-S signatures; libsecp256k1 only verifies low-S
+    signature.signature.normalize_s();
+    let message = Message::from_digest(sighash.to_byte_array());
+    Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature.signature, &pubkey.inner)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::block::Version as BlockVersion;
+    use bitcoin::consensus::encode::serialize;
+    use bitcoin::opcodes::all::OP_PUSHNUM_1;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::sighash::EcdsaSighashType;
+    use bitcoin::{BlockHash, CompactTarget, TxMerkleNode};
+
+    use super::*;
+
+    /// Signed block: header, coinbase and one more transaction
+    struct SignedBlock {
+        header: Header,
+        txdata: Vec<Transaction>,
+    }
+
+    fn key(seed: u8) -> (SecretKey, PublicKey) {
+        let secret = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let public = PublicKey::new(secret.public_key(&Secp256k1::signing_only()));
+        (secret, public)
+    }
+
+    /// `OP_1 <pubkey> OP_1 OP_CHECKMULTISIG`, the form of the default signet's challenge
+    fn multisig_challenge(public: &PublicKey) -> ScriptBuf {
+        Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_key(public)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn merkle_root(txdata: &[Transaction]) -> TxMerkleNode {
+        bitcoin::merkle_tree::calculate_root(txdata.iter().map(Transaction::compute_txid))
+            .unwrap()
+            .into()
+    }
+
+    /// Coinbase with a witness commitment output ending in `signet_push`
+    fn coinbase(signet_push: &[u8]) -> Transaction {
+        let mut commitment = WITNESS_COMMITMENT_PREFIX[2..].to_vec();
+        commitment.extend_from_slice(&[0x5a; 32]);
+        let mut commitment_script = Builder::new()
+            .push_opcode(OP_RETURN)
+            .push_slice(PushBytesBuf::try_from(commitment).unwrap());
+        if !signet_push.is_empty() {
+            commitment_script =
+                commitment_script.push_slice(PushBytesBuf::try_from(signet_push.to_vec()).unwrap());
+        }
+        Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new().push_int(1000).into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value: bitcoin::Amount::from_sat(50_0000_0000),
+                    script_pubkey: ScriptBuf::new(),
+                },
+                TxOut {
+                    value: bitcoin::Amount::ZERO,
+                    script_pubkey: commitment_script.into_script(),
+                },
+            ],
+        }
+    }
+
+    /// Signs a block for `challenge` following BIP325, as Bitcoin Core's signet miner
+    /// does: the signature covers the block with just the signet header in the
+    /// commitment
+    fn signed_block(challenge: &Script, secret: &SecretKey) -> SignedBlock {
+        let payment = Transaction {
+            version: Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_byte_array([7; 32]), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::from_sat(1000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let mut header = Header {
+            version: BlockVersion::from_consensus(0x2000_0000),
+            prev_blockhash: BlockHash::from_byte_array([3; 32]),
+            merkle_root: merkle_root(&[coinbase(&SIGNET_HEADER), payment.clone()]),
+            time: 1_700_000_000,
+            bits: CompactTarget::from_consensus(0x1e0377ae),
+            nonce: 0,
+        };
+
+        let mut block_data = serialize(&header.version);
+        block_data.extend(serialize(&header.prev_blockhash));
+        block_data.extend(serialize(&header.merkle_root));
+        block_data.extend(serialize(&header.time));
+        let to_spend = Transaction {
+            version: Version(0),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Builder::new()
+                    .push_opcode(OP_0)
+                    .push_slice(PushBytesBuf::try_from(block_data).unwrap())
+                    .into_script(),
+                sequence: Sequence(0),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: challenge.to_owned(),
+            }],
+        };
+        let mut spend = Transaction {
+            version: Version(0),
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence(0),
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+            }],
+        };
+        let sighash = SighashCache::new(&spend)
+            .legacy_signature_hash(0, challenge, EcdsaSighashType::All.to_u32())
+            .unwrap();
+        let signature = bitcoin::ecdsa::Signature::sighash_all(
+            Secp256k1::signing_only()
+                .sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), secret),
+        );
+        // CHECKMULTISIG pops an extra (empty) item
+        let mut script_sig = Builder::new();
+        if challenge.as_bytes().last() == Some(&OP_CHECKMULTISIG.to_u8()) {
+            script_sig = script_sig.push_opcode(OP_0);
+        }
+        spend.input[0].script_sig = script_sig
+            .push_slice(PushBytesBuf::try_from(signature.to_vec()).unwrap())
+            .into_script();
+
+        let mut signet_push = SIGNET_HEADER.to_vec();
+        signet_push.extend(serialize(&spend.input[0].script_sig));
+        signet_push.extend(serialize(&spend.input[0].witness));
+        let txdata = vec![coinbase(&signet_push), payment];
+        header.merkle_root = merkle_root(&txdata);
+        SignedBlock { header, txdata }
+    }
+
+    fn verify(challenge: &Script, block: &SignedBlock) -> Result<(), String> {
+        let mut verifier = SignetVerifier::new(challenge.as_bytes());
+        for tx in &block.txdata {
+            verifier.push(tx, hash::txid(tx));
+        }
+        verifier.verify(&block.header)
+    }
+
+    #[test]
+    fn test_multisig_solution() {
+        let (secret, public) = key(1);
+        let challenge = multisig_challenge(&public);
+        verify(&challenge, &signed_block(&challenge, &secret)).unwrap();
+    }
+
+    #[test]
+    fn test_single_key_solution() {
+        let (secret, public) = key(1);
+        let challenge = Builder::new()
+            .push_key(&public)
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        verify(&challenge, &signed_block(&challenge, &secret)).unwrap();
+    }
+
+    #[test]
+    fn test_wrong_challenge() {
+        let (secret, public) = key(1);
+        let block = signed_block(&multisig_challenge(&public), &secret);
+        let (_, other) = key(2);
+        assert_eq!(
+            verify(&multisig_challenge(&other), &block),
+            Err("Signet multisig signatures do not verify".to_string())
+        );
+    }
+
+    #[test]
+    fn test_modified_header() {
+        let (secret, public) = key(1);
+        let challenge = multisig_challenge(&public);
+        let mut block = signed_block(&challenge, &secret);
+        block.header.time += 1;
+        assert!(verify(&challenge, &block).is_err());
+    }
+
+    #[test]
+    fn test_missing_commitment() {
+        let (secret, public) = key(1);
+        let challenge = multisig_challenge(&public);
+        let mut block = signed_block(&challenge, &secret);
+        block.txdata[0].output.pop();
+        assert_eq!(
+            verify(&challenge, &block),
+            Err("Coinbase has no witness commitment".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_solution() {
+        let (secret, public) = key(1);
+        let mut block = signed_block(&multisig_challenge(&public), &secret);
+        block.txdata[0] = coinbase(&[]);
+        assert_eq!(
+            verify(&multisig_challenge(&public), &block),
+            Err("Empty signet scriptSig".to_string())
+        );
+        // Only OP_TRUE accepts a block without a solution
+        let op_true = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        verify(&op_true, &block).unwrap();
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+// Shared modules find it here, as on the host
+pub use crate::merkle_simple::MerkleProof;
+
 /// Proof strategy for processing Bitcoin blocks
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProofStrategy {
@@ -40,11 +43,39 @@ pub enum TransactionPattern {
     All,
 }
 
-/// Input data for the ZK proof - raw Bitcoin block
+/// Bitcoin network a block belongs to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BitcoinNetwork {
+    Mainnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    /// P2P message start bytes, also the record magic in `blk*.dat` files
+    ///
+    /// Committed to the journal so a proof can't be replayed on another chain.
+    pub const fn magic(self) -> [u8; 4] {
+        match self {
+            BitcoinNetwork::Mainnet => [0xf9, 0xbe, 0xb4, 0xd9],
+            BitcoinNetwork::Testnet4 => [0x1c, 0x16, 0x3f, 0x28],
+            BitcoinNetwork::Signet => [0x0a, 0x03, 0xcf, 0x40],
+            BitcoinNetwork::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+}
+
+/// Input data for the ZK proof
+///
+/// The consensus-encoded raw block follows this struct on the guest's stdin as plain
+/// bytes (`ExecutorEnvBuilder::write_slice`) so the guest can stream it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BitcoinBlockInput {
-    pub raw_block: Vec<u8>,
     pub block_height: u64,
+    pub network: BitcoinNetwork,
+    /// BIP325 challenge script, required on signet and rejected elsewhere
+    pub signet_challenge: Option<Vec<u8>>,
     pub strategy: ProofStrategy,
 }
 
@@ -84,12 +115,18 @@ pub struct BitcoinBlockProof {
     pub block_hash: String,
     /// Bitcoin block height
     pub block_height: u64,
+    /// Magic of the network the block was proven on (see [`BitcoinNetwork::magic`])
+    pub network_magic: [u8; 4],
+    /// SHA-256 of the signet challenge the block solution was checked against
+    pub signet_challenge_hash: Option<[u8; 32]>,
     /// Strategy used to generate this proof
     pub strategy: ProofStrategy,
     /// Matching transaction IDs (each committed in ZK proof)
     pub matching_transactions: Vec<MatchingTransaction>,
     /// Merkle proofs for pointed transactions (only for pointing strategy)
-    pub merkle_proofs: Vec<crate::merkle_simple::MerkleProof>,
+    pub merkle_proofs: Vec<MerkleProof>,
+    /// Inclusion multiproof for all matching transactions (only for searching strategy)
+    pub merkle_multiproof: Option<crate::multiproof::MerkleMultiproof>,
     /// Total number of transactions in the block
     pub total_transactions: u32,
     /// Number of matching transactions
@@ -111,6 +148,56 @@ impl BitcoinBlockProof {
     }
 }
 
+/// Reasons the guest rejects a block or strategy
+///
+/// These are committed to the journal (see [`BlockProofJournal`]) so a rejection is
+/// itself provable, e.g. "txid X is not of type Burn".
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProofError {
+    /// The raw block could not be decoded, or its transaction list is mutated
+    MalformedBlock(String),
+    /// The block's transactions do not hash to the header's merkle root
+    MerkleRootMismatch,
+    /// The requested strategy is invalid (e.g. an unparseable txid)
+    InvalidStrategy(String),
+    /// The pointed transaction is not in the block
+    TransactionNotFound { txid: String },
+    /// The pointed transaction matches no Core Lane pattern
+    NotCoreLaneTransaction { txid: String },
+    /// The pointed transaction is a different Core Lane type than expected
+    TypeMismatch {
+        txid: String,
+        expected: TransactionType,
+        actual: TransactionType,
+    },
+    /// A generated Merkle proof did not verify against the merkle root
+    MerkleProofFailed,
+    /// The header's target is out of range for the network or its hash doesn't meet it
+    InvalidProofOfWork,
+    /// The signet block solution is missing or doesn't satisfy the challenge
+    InvalidSignetSolution(String),
+}
+
+/// A rejected block, committed in place of a [`BitcoinBlockProof`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockProofFailure {
+    /// Bitcoin block height
+    pub block_height: u64,
+    /// Bitcoin block hash (if the header could be parsed)
+    pub block_hash: Option<String>,
+    /// Magic of the network the block was proven on
+    pub network_magic: [u8; 4],
+    /// SHA-256 of the signet challenge, if any
+    pub signet_challenge_hash: Option<[u8; 32]>,
+    /// Strategy that was requested
+    pub strategy: ProofStrategy,
+    /// Why the block was rejected
+    pub error: ProofError,
+}
+
+/// Journal committed by the guest: the proof, or the provable reason there is none
+pub type BlockProofJournal = Result<BitcoinBlockProof, BlockProofFailure>;
+
 /// Core Lane transaction patterns to match
 #[derive(Debug, Clone)]
 pub struct CoreLanePatterns {
@@ -118,6 +205,20 @@ pub struct CoreLanePatterns {
     pub da_prefix: Vec<u8>,   // Core Lane DA transaction prefix
 }
 
+impl CoreLanePatterns {
+    /// Patterns Core Lane uses on `network`
+    ///
+    /// Every network shares the mainnet prefixes today; test networks can diverge here.
+    pub fn for_network(network: BitcoinNetwork) -> Self {
+        match network {
+            BitcoinNetwork::Mainnet
+            | BitcoinNetwork::Testnet4
+            | BitcoinNetwork::Signet
+            | BitcoinNetwork::Regtest => Self::default(),
+        }
+    }
+}
+
 impl Default for CoreLanePatterns {
     fn default() -> Self {
         Self {
@@ -0,0 +1,79 @@
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::Block;
+use risc0_zkvm::guest::env;
+
+// Share the exact hashing code the main guest runs
+#[path = "../../guest/src/hash.rs"]
+mod hash;
+
+/// Builds a Merkle root level by level with the given parent hash function
+fn merkle_root(
+    mut level: Vec<[u8; 32]>,
+    parent: impl Fn(&[u8; 32], &[u8; 32]) -> [u8; 32],
+) -> [u8; 32] {
+    while level.len() > 1 {
+        if !level.len().is_multiple_of(2) {
+            level.push(*level.last().unwrap());
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| parent(&pair[0], &pair[1]))
+            .collect();
+    }
+    level[0]
+}
+
+fn software_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(left);
+    engine.input(right);
+    sha256d::Hash::from_engine(engine).to_byte_array()
+}
+
+/// Compares the cycle cost of the software `bitcoin` hashing path against the
+/// accelerated path in `hash.rs` for the block hash, every txid and the Merkle root.
+///
+/// Commits `(transaction_count, software_cycles, accelerated_cycles)`.
+fn main() {
+    let raw_block: Vec<u8> = env::read();
+    let block: Block =
+        bitcoin::consensus::deserialize(&raw_block).expect("Failed to parse Bitcoin block");
+
+    let start = env::cycle_count();
+    let software_hash = block.block_hash().to_byte_array();
+    let software_txids: Vec<[u8; 32]> = block
+        .txdata
+        .iter()
+        .map(|tx| tx.compute_txid().to_byte_array())
+        .collect();
+    let software_root = merkle_root(software_txids.clone(), software_parent);
+    let software_cycles = env::cycle_count() - start;
+
+    let start = env::cycle_count();
+    let accelerated_hash = hash::block_hash(&block.header);
+    let accelerated_txids: Vec<[u8; 32]> = block.txdata.iter().map(hash::txid).collect();
+    let accelerated_root = merkle_root(accelerated_txids.clone(), hash::merkle_parent);
+    let accelerated_cycles = env::cycle_count() - start;
+
+    assert_eq!(software_hash, accelerated_hash, "block hash mismatch");
+    assert_eq!(software_txids, accelerated_txids, "txid mismatch");
+    assert_eq!(software_root, accelerated_root, "merkle root mismatch");
+    assert_eq!(
+        accelerated_root,
+        block.header.merkle_root.to_byte_array(),
+        "merkle root does not match header"
+    );
+
+    env::log(&format!(
+        "Hashed {} transactions: software {} cycles, accelerated {} cycles",
+        block.txdata.len(),
+        software_cycles,
+        accelerated_cycles
+    ));
+
+    env::commit(&(
+        block.txdata.len() as u32,
+        software_cycles,
+        accelerated_cycles,
+    ));
+}
@@ -3,3 +3,25 @@ include!(concat!(env!("OUT_DIR"), "/methods.rs"));
 // Re-export types module
 // This allows the host and core-lane to import these types
 pub mod types;
+
+// Transaction matching shared with the guest, for native pre-scans on the host
+#[path = "../guest/src/matching.rs"]
+pub mod matching;
+
+// Merkle multiproofs shared with the guest, so the host reads them exactly as the
+// guest builds and verifies them
+#[path = "../guest/src/multiproof.rs"]
+pub mod multiproof;
+
+// `merkle_parent` for the shared modules; the guest's uses its SHA-256 accelerator
+mod hash {
+    use bitcoin::hashes::{sha256d, Hash};
+
+    /// Hashes two child nodes into their Merkle parent (double SHA-256)
+    pub fn merkle_parent(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let mut data = [0u8; 64];
+        data[..32].copy_from_slice(left);
+        data[32..].copy_from_slice(right);
+        sha256d::Hash::hash(&data).to_byte_array()
+    }
+}
@@ -1,5 +1,14 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::hashes::Hash;
+use risc0_zkvm::{InnerReceipt, Receipt};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+pub use crate::multiproof::MerkleMultiproof;
+
 /// Proof strategy for processing Bitcoin blocks
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ProofStrategy {
@@ -40,11 +49,39 @@ pub enum TransactionPattern {
     All,
 }
 
-/// Input data for the ZK proof - raw Bitcoin block
+/// Bitcoin network a block belongs to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BitcoinNetwork {
+    Mainnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    /// P2P message start bytes, also the record magic in `blk*.dat` files
+    ///
+    /// Committed to the journal so a proof can't be replayed on another chain.
+    pub const fn magic(self) -> [u8; 4] {
+        match self {
+            BitcoinNetwork::Mainnet => [0xf9, 0xbe, 0xb4, 0xd9],
+            BitcoinNetwork::Testnet4 => [0x1c, 0x16, 0x3f, 0x28],
+            BitcoinNetwork::Signet => [0x0a, 0x03, 0xcf, 0x40],
+            BitcoinNetwork::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+}
+
+/// Input data for the ZK proof
+///
+/// The consensus-encoded raw block follows this struct on the guest's stdin as plain
+/// bytes (`ExecutorEnvBuilder::write_slice`) so the guest can stream it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BitcoinBlockInput {
-    pub raw_block: Vec<u8>,
     pub block_height: u64,
+    pub network: BitcoinNetwork,
+    /// BIP325 challenge script, required on signet and rejected elsewhere
+    pub signet_challenge: Option<Vec<u8>>,
     pub strategy: ProofStrategy,
 }
 
@@ -95,12 +132,19 @@ pub struct BitcoinBlockProof {
     pub block_hash: String,
     /// Bitcoin block height
     pub block_height: u64,
+    /// Magic of the network the block was proven on (see [`BitcoinNetwork::magic`])
+    pub network_magic: [u8; 4],
+    /// SHA-256 of the signet challenge the block solution was checked against
+    pub signet_challenge_hash: Option<[u8; 32]>,
     /// Strategy used to generate this proof
     pub strategy: ProofStrategy,
     /// Matching transaction IDs (each committed in ZK proof)
     pub matching_transactions: Vec<MatchingTransaction>,
     /// Merkle proofs for pointed transactions (only for pointing strategy)
     pub merkle_proofs: Vec<MerkleProof>,
+    /// Inclusion multiproof for all matching transactions (only for searching strategy)
+    #[serde(default)] // Missing from proofs written before multiproofs
+    pub merkle_multiproof: Option<MerkleMultiproof>,
     /// Total number of transactions in the block
     pub total_transactions: u32,
     /// Number of matching transactions
@@ -110,18 +154,334 @@ pub struct BitcoinBlockProof {
 impl BitcoinBlockProof {
     /// Serialize to CBOR format for efficient storage
     pub fn to_cbor(&self) -> Result<Vec<u8>, String> {
-        let mut buffer = Vec::new();
-        ciborium::into_writer(self, &mut buffer)
-            .map_err(|e| format!("CBOR serialization failed: {}", e))?;
-        Ok(buffer)
+        to_cbor(self)
     }
 
     /// Deserialize from CBOR format
     pub fn from_cbor(data: &[u8]) -> Result<Self, String> {
-        ciborium::from_reader(data).map_err(|e| format!("CBOR deserialization failed: {}", e))
+        from_cbor(data)
+    }
+
+    /// Txids of the matching transactions in internal byte order, the leaves of
+    /// [`Self::merkle_multiproof`]
+    pub fn matching_txids(&self) -> Result<Vec<[u8; 32]>, String> {
+        self.matching_transactions
+            .iter()
+            .map(|tx| {
+                bitcoin::Txid::from_str(&tx.txid)
+                    .map(|txid| txid.to_byte_array())
+                    .map_err(|e| format!("Invalid txid {}: {}", tx.txid, e))
+            })
+            .collect()
     }
 }
 
+/// Reasons the guest rejects a block or strategy
+///
+/// These are committed to the journal (see [`BlockProofJournal`]) so a rejection is
+/// itself provable, e.g. "txid X is not of type Burn".
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProofError {
+    /// The raw block could not be decoded, or its transaction list is mutated
+    MalformedBlock(String),
+    /// The block's transactions do not hash to the header's merkle root
+    MerkleRootMismatch,
+    /// The requested strategy is invalid (e.g. an unparseable txid)
+    InvalidStrategy(String),
+    /// The pointed transaction is not in the block
+    TransactionNotFound { txid: String },
+    /// The pointed transaction matches no Core Lane pattern
+    NotCoreLaneTransaction { txid: String },
+    /// The pointed transaction is a different Core Lane type than expected
+    TypeMismatch {
+        txid: String,
+        expected: TransactionType,
+        actual: TransactionType,
+    },
+    /// A generated Merkle proof did not verify against the merkle root
+    MerkleProofFailed,
+    /// The header's target is out of range for the network or its hash doesn't meet it
+    InvalidProofOfWork,
+    /// The signet block solution is missing or doesn't satisfy the challenge
+    InvalidSignetSolution(String),
+}
+
+/// A rejected block, committed in place of a [`BitcoinBlockProof`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockProofFailure {
+    /// Bitcoin block height
+    pub block_height: u64,
+    /// Bitcoin block hash (if the header could be parsed)
+    pub block_hash: Option<String>,
+    /// Magic of the network the block was proven on
+    pub network_magic: [u8; 4],
+    /// SHA-256 of the signet challenge, if any
+    pub signet_challenge_hash: Option<[u8; 32]>,
+    /// Strategy that was requested
+    pub strategy: ProofStrategy,
+    /// Why the block was rejected
+    pub error: ProofError,
+}
+
+/// Journal committed by the guest: the proof, or the provable reason there is none
+pub type BlockProofJournal = Result<BitcoinBlockProof, BlockProofFailure>;
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::MalformedBlock(reason) => write!(f, "malformed block: {}", reason),
+            ProofError::MerkleRootMismatch => {
+                write!(f, "transactions do not match the header merkle root")
+            }
+            ProofError::InvalidStrategy(reason) => write!(f, "invalid strategy: {}", reason),
+            ProofError::TransactionNotFound { txid } => {
+                write!(f, "transaction {} not found in block", txid)
+            }
+            ProofError::NotCoreLaneTransaction { txid } => {
+                write!(f, "transaction {} matches no Core Lane pattern", txid)
+            }
+            ProofError::TypeMismatch {
+                txid,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "transaction {} is not of type {:?} (found {:?})",
+                txid, expected, actual
+            ),
+            ProofError::MerkleProofFailed => {
+                write!(f, "generated Merkle proof failed verification")
+            }
+            ProofError::InvalidProofOfWork => {
+                write!(f, "header does not meet the network's proof of work")
+            }
+            ProofError::InvalidSignetSolution(reason) => {
+                write!(f, "invalid signet block solution: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+impl fmt::Display for BlockProofFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {} rejected: {}", self.block_height, self.error)
+    }
+}
+
+impl std::error::Error for BlockProofFailure {}
+
+impl fmt::Display for BitcoinNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BitcoinNetwork::Mainnet => "mainnet",
+            BitcoinNetwork::Testnet4 => "testnet4",
+            BitcoinNetwork::Signet => "signet",
+            BitcoinNetwork::Regtest => "regtest",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for BitcoinNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" | "bitcoin" => Ok(BitcoinNetwork::Mainnet),
+            "testnet4" => Ok(BitcoinNetwork::Testnet4),
+            "signet" => Ok(BitcoinNetwork::Signet),
+            "regtest" => Ok(BitcoinNetwork::Regtest),
+            _ => Err(format!(
+                "Unknown network {} (expected mainnet, testnet4, signet or regtest)",
+                s
+            )),
+        }
+    }
+}
+
+/// Current version of the [`ProofBundle`] file format
+pub const PROOF_BUNDLE_VERSION: u32 = 1;
+
+/// Kind of risc0 receipt held in a [`ProofBundle`]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReceiptKind {
+    Composite,
+    Succinct,
+    Groth16,
+    /// Dev-mode receipt (RISC0_DEV_MODE), not a real proof
+    Fake,
+}
+
+impl ReceiptKind {
+    /// Classify a receipt by its inner proof system
+    pub fn of(receipt: &Receipt) -> Self {
+        match &receipt.inner {
+            InnerReceipt::Composite(_) => ReceiptKind::Composite,
+            InnerReceipt::Succinct(_) => ReceiptKind::Succinct,
+            InnerReceipt::Groth16(_) => ReceiptKind::Groth16,
+            _ => ReceiptKind::Fake,
+        }
+    }
+}
+
+/// When and how a [`ProofBundle`] was produced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleMetadata {
+    /// Unix timestamp (seconds) when the bundle was created
+    pub created_at: u64,
+    /// Version of the host that produced the bundle
+    pub host_version: String,
+    /// risc0-zkvm version used for proving
+    pub risc0_version: String,
+    /// Prover backend (e.g. "local" or "bonsai")
+    pub prover: String,
+}
+
+/// Self-contained proof file: the receipt, its decoded journal and everything needed
+/// to check it
+///
+/// Stored as CBOR. This is the format Core Lane's proof cache reads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofBundle {
+    /// File format version ([`PROOF_BUNDLE_VERSION`] when written)
+    pub format_version: u32,
+    /// Image ID of the guest that produced the receipt
+    pub image_id: [u32; 8],
+    /// Kind of receipt in `receipt`
+    pub receipt_kind: ReceiptKind,
+    /// Bitcoin network of the proven block
+    pub network: BitcoinNetwork,
+    /// Decoded journal (the proof, or the provable rejection)
+    pub journal: BlockProofJournal,
+    /// The risc0 receipt
+    pub receipt: Receipt,
+    /// Creation metadata
+    pub metadata: BundleMetadata,
+}
+
+impl ProofBundle {
+    /// Bundle a receipt, decoding its journal
+    pub fn new(
+        receipt: Receipt,
+        image_id: [u32; 8],
+        network: BitcoinNetwork,
+        metadata: BundleMetadata,
+    ) -> Result<Self, String> {
+        let journal: BlockProofJournal = receipt
+            .journal
+            .decode()
+            .map_err(|e| format!("Journal decoding failed: {}", e))?;
+        Ok(Self {
+            format_version: PROOF_BUNDLE_VERSION,
+            image_id,
+            receipt_kind: ReceiptKind::of(&receipt),
+            network,
+            journal,
+            receipt,
+            metadata,
+        })
+    }
+
+    /// Serialize to CBOR format
+    pub fn to_cbor(&self) -> Result<Vec<u8>, String> {
+        to_cbor(self)
+    }
+
+    /// Deserialize from CBOR format, rejecting unsupported format versions
+    pub fn from_cbor(data: &[u8]) -> Result<Self, String> {
+        let bundle: Self = from_cbor(data)?;
+        if bundle.format_version != PROOF_BUNDLE_VERSION {
+            return Err(format!(
+                "Unsupported proof bundle version {} (expected {})",
+                bundle.format_version, PROOF_BUNDLE_VERSION
+            ));
+        }
+        Ok(bundle)
+    }
+
+    /// Read a bundle from a file
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_cbor(&data)
+    }
+
+    /// Write the bundle to a file
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_cbor()?)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// The committed proof, if the guest accepted the block
+    pub fn proof(&self) -> Option<&BitcoinBlockProof> {
+        self.journal.as_ref().ok()
+    }
+
+    /// The committed rejection, if the guest rejected the block
+    pub fn failure(&self) -> Option<&BlockProofFailure> {
+        self.journal.as_ref().err()
+    }
+
+    /// Height of the proven block
+    pub fn block_height(&self) -> u64 {
+        match &self.journal {
+            Ok(proof) => proof.block_height,
+            Err(failure) => failure.block_height,
+        }
+    }
+
+    /// Network magic committed in the journal
+    pub fn network_magic(&self) -> [u8; 4] {
+        match &self.journal {
+            Ok(proof) => proof.network_magic,
+            Err(failure) => failure.network_magic,
+        }
+    }
+
+    /// Verify the receipt against `image_id` and check that the decoded journal is
+    /// exactly what the receipt commits to, on the network the bundle claims
+    pub fn verify(&self, image_id: [u32; 8]) -> Result<(), String> {
+        if self.image_id != image_id {
+            return Err("Bundle image ID does not match the expected image ID".to_string());
+        }
+        if self.network_magic() != self.network.magic() {
+            return Err(format!(
+                "Journal network magic {:02x?} does not match the bundle network {}",
+                self.network_magic(),
+                self.network
+            ));
+        }
+        self.receipt
+            .verify(image_id)
+            .map_err(|e| format!("Receipt verification failed: {}", e))?;
+
+        let words = risc0_zkvm::serde::to_vec(&self.journal)
+            .map_err(|e| format!("Journal encoding failed: {}", e))?;
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        if bytes != self.receipt.journal.bytes {
+            return Err("Bundle journal does not match the receipt journal".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Serialize any value to CBOR
+fn to_cbor<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    let mut buffer = Vec::new();
+    ciborium::into_writer(value, &mut buffer)
+        .map_err(|e| format!("CBOR serialization failed: {}", e))?;
+    Ok(buffer)
+}
+
+/// Deserialize any value from CBOR
+fn from_cbor<T: DeserializeOwned>(data: &[u8]) -> Result<T, String> {
+    ciborium::from_reader(data).map_err(|e| format!("CBOR deserialization failed: {}", e))
+}
+
 /// Core Lane transaction patterns to match
 #[derive(Debug, Clone)]
 pub struct CoreLanePatterns {
@@ -129,6 +489,20 @@ pub struct CoreLanePatterns {
     pub da_prefix: Vec<u8>,   // Core Lane DA transaction prefix
 }
 
+impl CoreLanePatterns {
+    /// Patterns Core Lane uses on `network`
+    ///
+    /// Every network shares the mainnet prefixes today; test networks can diverge here.
+    pub fn for_network(network: BitcoinNetwork) -> Self {
+        match network {
+            BitcoinNetwork::Mainnet
+            | BitcoinNetwork::Testnet4
+            | BitcoinNetwork::Signet
+            | BitcoinNetwork::Regtest => Self::default(),
+        }
+    }
+}
+
 impl Default for CoreLanePatterns {
     fn default() -> Self {
         Self {
@@ -137,3 +511,167 @@ impl Default for CoreLanePatterns {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use risc0_zkvm::{FakeReceipt, ReceiptClaim};
+
+    const IMAGE_ID: [u32; 8] = [7; 8];
+
+    fn proof() -> BitcoinBlockProof {
+        BitcoinBlockProof {
+            block_hash: "00000000000000000001d95d5c3b832c7a78262500f9c5057b889b0f35702c9b"
+                .to_string(),
+            block_height: 916202,
+            network_magic: BitcoinNetwork::Mainnet.magic(),
+            signet_challenge_hash: None,
+            strategy: ProofStrategy::Searching(SearchingProof {
+                pattern: TransactionPattern::All,
+            }),
+            matching_transactions: vec![MatchingTransaction {
+                txid: "ab".repeat(32),
+                tx_type: TransactionType::Burn,
+            }],
+            merkle_proofs: Vec::new(),
+            merkle_multiproof: None,
+            total_transactions: 3362,
+            matching_count: 1,
+        }
+    }
+
+    /// Bundle around a dev-mode receipt committing to `journal`
+    fn bundle(journal: &BlockProofJournal) -> ProofBundle {
+        // Fake receipts only verify in dev mode
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        let words = risc0_zkvm::serde::to_vec(journal).unwrap();
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        let receipt = Receipt::new(
+            InnerReceipt::Fake(FakeReceipt::new(ReceiptClaim::ok(IMAGE_ID, bytes.clone()))),
+            bytes,
+        );
+        let metadata = BundleMetadata {
+            created_at: 1_700_000_000,
+            host_version: "test".to_string(),
+            risc0_version: "test".to_string(),
+            prover: "local".to_string(),
+        };
+        ProofBundle::new(receipt, IMAGE_ID, BitcoinNetwork::Mainnet, metadata).unwrap()
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let bundle = bundle(&Ok(proof()));
+        bundle.verify(IMAGE_ID).unwrap();
+
+        let decoded = ProofBundle::from_cbor(&bundle.to_cbor().unwrap()).unwrap();
+        assert_eq!(decoded.format_version, PROOF_BUNDLE_VERSION);
+        assert_eq!(decoded.image_id, IMAGE_ID);
+        assert_eq!(decoded.receipt_kind, ReceiptKind::Fake);
+        assert_eq!(decoded.network, BitcoinNetwork::Mainnet);
+        assert_eq!(decoded.metadata.created_at, 1_700_000_000);
+        assert_eq!(decoded.block_height(), 916202);
+        assert_eq!(
+            to_cbor(&decoded.journal).unwrap(),
+            to_cbor(&bundle.journal).unwrap()
+        );
+        decoded.verify(IMAGE_ID).unwrap();
+    }
+
+    #[test]
+    fn test_bundle_rejection_round_trip() {
+        let failure = BlockProofFailure {
+            block_height: 916202,
+            block_hash: None,
+            network_magic: BitcoinNetwork::Mainnet.magic(),
+            signet_challenge_hash: None,
+            strategy: proof().strategy,
+            error: ProofError::MerkleRootMismatch,
+        };
+        let bundle = bundle(&Err(failure));
+
+        let decoded = ProofBundle::from_cbor(&bundle.to_cbor().unwrap()).unwrap();
+        assert!(decoded.proof().is_none());
+        assert_eq!(
+            decoded.failure().map(|failure| &failure.error),
+            Some(&ProofError::MerkleRootMismatch)
+        );
+        decoded.verify(IMAGE_ID).unwrap();
+    }
+
+    #[test]
+    fn test_bundle_rejects_other_versions() {
+        let mut bundle = bundle(&Ok(proof()));
+        bundle.format_version = PROOF_BUNDLE_VERSION + 1;
+
+        let error = ProofBundle::from_cbor(&bundle.to_cbor().unwrap()).unwrap_err();
+        assert!(
+            error.contains("Unsupported proof bundle version"),
+            "{}",
+            error
+        );
+        assert!(ProofBundle::from_cbor(b"not a bundle").is_err());
+    }
+
+    #[test]
+    fn test_bundle_verify_rejects_tampering() {
+        let bundle = bundle(&Ok(proof()));
+        assert!(bundle.verify([8; 8]).is_err());
+
+        let mut tampered = bundle.clone();
+        tampered.image_id = [8; 8];
+        assert!(tampered.verify(IMAGE_ID).is_err());
+
+        // A decoded journal that no longer matches the committed bytes
+        let mut tampered = bundle.clone();
+        if let Ok(proof) = &mut tampered.journal {
+            proof.matching_transactions[0].tx_type = TransactionType::Fill;
+        }
+        let error = tampered.verify(IMAGE_ID).unwrap_err();
+        assert!(
+            error.contains("does not match the receipt journal"),
+            "{}",
+            error
+        );
+
+        let mut tampered = bundle;
+        tampered.receipt.journal.bytes[0] ^= 1;
+        assert!(tampered.verify(IMAGE_ID).is_err());
+    }
+
+    #[test]
+    fn test_bundle_verify_rejects_other_network() {
+        // A mainnet journal relabelled as a testnet4 bundle
+        let mut bundle = bundle(&Ok(proof()));
+        bundle.network = BitcoinNetwork::Testnet4;
+        let error = bundle.verify(IMAGE_ID).unwrap_err();
+        assert!(
+            error.contains("does not match the bundle network testnet4"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_network_names() {
+        for network in [
+            BitcoinNetwork::Mainnet,
+            BitcoinNetwork::Testnet4,
+            BitcoinNetwork::Signet,
+            BitcoinNetwork::Regtest,
+        ] {
+            assert_eq!(network.to_string().parse(), Ok(network));
+        }
+        assert_eq!("bitcoin".parse(), Ok(BitcoinNetwork::Mainnet));
+        assert!("testnet".parse::<BitcoinNetwork>().is_err());
+        assert!("Mainnet".parse::<BitcoinNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_network_magic() {
+        assert_eq!(BitcoinNetwork::Mainnet.magic(), [0xf9, 0xbe, 0xb4, 0xd9]);
+        assert_eq!(BitcoinNetwork::Testnet4.magic(), [0x1c, 0x16, 0x3f, 0x28]);
+        assert_eq!(BitcoinNetwork::Signet.magic(), [0x0a, 0x03, 0xcf, 0x40]);
+        assert_eq!(BitcoinNetwork::Regtest.magic(), [0xfa, 0xbf, 0xb5, 0xda]);
+    }
+}